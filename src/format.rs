@@ -0,0 +1,648 @@
+use crate::model::{
+  node::*,
+  value::{Expression, Value},
+};
+use std::cell::Ref;
+
+// Attributes the parser derives from Markdown syntax rather than annotations,
+// for each type of node that has them
+fn syntax_attributes(kind: &Type) -> &'static [&'static str] {
+  match kind {
+    Type::Heading => &["level"],
+    Type::List => &["ordered", "number"],
+    Type::Link => &["href", "title"],
+    Type::Image => &["src", "title"],
+    Type::Fence => &["language", "content"],
+    _ => &[],
+  }
+}
+
+// Returns `None` for trees with syntax errors or unbalanced tags, since the
+// source they came from can't be printed back from the nodes
+pub fn format(node: &Node) -> Option<String> {
+  if has_errors(node) {
+    return None;
+  }
+
+  let mut output = block(node);
+  if !output.is_empty() {
+    output.push('\n');
+  }
+  Some(output)
+}
+
+fn has_errors(node: &Node) -> bool {
+  node.kind == Type::Error || node.errors.is_some() || children(node).any(|x| has_errors(&x))
+}
+
+fn identifier(value: &str) -> bool {
+  let mut chars = value.chars();
+  matches!(chars.next(), Some(ch) if ch.is_ascii_alphabetic())
+    && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+}
+
+fn quote(value: &str) -> String {
+  format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn key(key: &str) -> String {
+  if identifier(key) {
+    key.to_string()
+  } else {
+    quote(key)
+  }
+}
+
+fn value(value: &Value) -> String {
+  match value {
+    Value::Hash(items) => {
+      let items: Vec<_> = items
+        .iter()
+        .map(|(name, item)| format!("{}: {}", key(name), self::value(item)))
+        .collect();
+      format!("{{{}}}", items.join(", "))
+    }
+    Value::Array(items) => {
+      let items: Vec<_> = items.iter().map(self::value).collect();
+      format!("[{}]", items.join(", "))
+    }
+    Value::String(string) => quote(string),
+    Value::Number(number) => number.to_string(),
+    Value::Boolean(boolean) => boolean.to_string(),
+    Value::Expression(_, expression) => self::expression(expression),
+    Value::Undefined | Value::Null => "null".into(),
+  }
+}
+
+fn expression(expression: &Expression) -> String {
+  match expression {
+    Expression::Variable(sigil, path) => {
+      let mut output = sigil.to_string();
+      for (index, segment) in path.iter().enumerate() {
+        match segment {
+          Value::String(name) if index == 0 => output.push_str(name),
+          Value::String(name) if identifier(name) => {
+            output.push('.');
+            output.push_str(name);
+          }
+          segment => output.push_str(&format!("[{}]", value(segment))),
+        }
+      }
+      output
+    }
+    Expression::Function(name, parameters, ..) => {
+      let parameters: Vec<_> = parameters
+        .iter()
+        .map(|(name, parameter)| match name.parse::<usize>() {
+          Ok(_) => value(parameter),
+          Err(_) => format!("{}={}", name, value(parameter)),
+        })
+        .collect();
+      format!("{}({})", name, parameters.join(", "))
+    }
+  }
+}
+
+fn attributes<'b, 'a: 'b>(attrs: impl Iterator<Item = (&'b str, &'b Value<'a>)>) -> Vec<String> {
+  let mut output = Vec::new();
+
+  for (name, attr) in attrs {
+    match (name, attr) {
+      ("primary", attr) => output.insert(0, value(attr)),
+      ("id", Value::String(id)) if identifier(id) => output.push(format!("#{}", id)),
+      ("class", Value::Hash(classes))
+        if classes
+          .iter()
+          .all(|(class, enabled)| identifier(class) && *enabled == Value::Boolean(true)) =>
+      {
+        output.extend(classes.keys().map(|class| format!(".{}", class)))
+      }
+      (name, attr) => output.push(format!("{}={}", name, value(attr))),
+    }
+  }
+
+  output
+}
+
+fn annotation(node: &Node) -> String {
+  let attrs = node
+    .attributes
+    .iter()
+    .flatten()
+    .filter(|(name, _)| !syntax_attributes(&node.kind).contains(&name.as_ref()))
+    .map(|(name, attr)| (name.as_ref(), attr));
+
+  match attributes(attrs) {
+    attrs if attrs.is_empty() => String::new(),
+    attrs => format!(" {{% {} %}}", attrs.join(" ")),
+  }
+}
+
+fn open_tag(node: &Node) -> String {
+  let name = node.tag.as_deref().unwrap_or_default();
+  let attrs = node
+    .attributes
+    .iter()
+    .flatten()
+    .map(|(name, attr)| (name.as_ref(), attr));
+
+  match attributes(attrs) {
+    attrs if attrs.is_empty() => format!("{{% {}", name),
+    attrs => format!("{{% {} {}", name, attrs.join(" ")),
+  }
+}
+
+fn children<'b, 'a>(node: &'b Node<'a>) -> impl Iterator<Item = Ref<'b, Node<'a>>> + 'b {
+  node.children.iter().flatten().map(|child| child.borrow())
+}
+
+// Partials carry the document they include as children, which belongs to
+// another file, so they're always written as self-closing
+fn self_closing(node: &Node) -> bool {
+  node.tag.as_deref() == Some("partial") || node.children.iter().flatten().next().is_none()
+}
+
+fn blocks(node: &Node, separator: &str) -> String {
+  children(node)
+    .map(|child| block(&child))
+    .filter(|child| !child.is_empty())
+    .collect::<Vec<_>>()
+    .join(separator)
+}
+
+fn indent(content: &str, first: &str, rest: &str) -> String {
+  content
+    .split('\n')
+    .enumerate()
+    .map(|(index, line)| match (index, line) {
+      (0, line) => format!("{}{}", first, line),
+      (_, "") => String::new(),
+      (_, line) => format!("{}{}", rest, line),
+    })
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+fn block(node: &Node) -> String {
+  match node.kind {
+    Type::Document => {
+      let content = blocks(node, "\n\n");
+      match frontmatter(node) {
+        Some(frontmatter) if content.is_empty() => frontmatter,
+        Some(frontmatter) => format!("{}\n\n{}", frontmatter, content),
+        None => content,
+      }
+    }
+    Type::Heading => {
+      let level = match node.attribute("level") {
+        Some(Value::Number(level)) => *level as usize,
+        _ => 1,
+      };
+      format!("{} {}", "#".repeat(level), inline_block(node, "\n"))
+    }
+    Type::Paragraph => inline_block(node, "\n"),
+    Type::Blockquote => {
+      let content = blocks(node, "\n\n");
+      content
+        .split('\n')
+        .map(|line| {
+          if line.is_empty() {
+            ">".into()
+          } else {
+            format!("> {}", line)
+          }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+    }
+    Type::Fence => fence(node),
+    Type::List => list(node),
+    Type::Table => table(node),
+    Type::Rule => "---".into(),
+    Type::Tag(false) if self_closing(node) => format!("{} /%}}", open_tag(node)),
+    Type::Tag(false) => format!(
+      "{} %}}\n{}\n{{% /{} %}}",
+      open_tag(node),
+      blocks(node, "\n\n"),
+      node.tag.as_deref().unwrap_or_default()
+    ),
+    _ if node.kind.is_inline() || node.kind == Type::Inline => inline(node),
+    _ => blocks(node, "\n\n"),
+  }
+}
+
+// Writes the children of a block that holds inline content. Images are block
+// nodes in the tree but sit on the same line as the text around them, and the
+// annotation for the block goes right after that first line of content.
+fn inline_block(node: &Node, separator: &str) -> String {
+  let mut parts: Vec<String> = Vec::new();
+  let mut inline_run = false;
+
+  for child in children(node) {
+    match child.kind {
+      Type::Inline | Type::Image if inline_run => {
+        if let Some(last) = parts.last_mut() {
+          last.push_str(&inline(&child));
+        }
+      }
+      Type::Inline | Type::Image => {
+        parts.push(inline(&child));
+        inline_run = true;
+      }
+      _ => {
+        parts.push(block(&child));
+        inline_run = false;
+      }
+    }
+  }
+
+  let annotation = annotation(node);
+  match parts.first_mut() {
+    Some(first) if first.ends_with(' ') => first.push_str(annotation.trim_start()),
+    Some(first) => first.push_str(&annotation),
+    None => parts.push(annotation.trim_start().to_string()),
+  }
+
+  parts.join(separator)
+}
+
+fn frontmatter(node: &Node) -> Option<String> {
+  let kind = node
+    .frontmatter_source
+    .as_ref()
+    .map_or(FrontmatterKind::Yaml, |x| x.kind);
+  let source = match node.attribute("frontmatter")? {
+    Value::String(source) => source.to_string(),
+    values => match kind {
+      FrontmatterKind::Yaml => serde_yaml::to_string(values)
+        .ok()?
+        .trim_start_matches("---\n")
+        .to_string(),
+      FrontmatterKind::Toml => toml::to_string(values).ok()?,
+      FrontmatterKind::Json => serde_json::to_string_pretty(values).ok()?,
+    },
+  };
+
+  let fence = match kind {
+    FrontmatterKind::Yaml => "---",
+    FrontmatterKind::Toml => "+++",
+    FrontmatterKind::Json => ";;;",
+  };
+
+  Some(format!("{}\n{}\n{}", fence, source.trim(), fence))
+}
+
+fn fence(node: &Node) -> String {
+  let content = match node.attribute("content") {
+    Some(Value::String(content)) => content.as_ref(),
+    _ => "",
+  };
+
+  let longest = content
+    .lines()
+    .map(|line| {
+      line
+        .trim_start()
+        .chars()
+        .take_while(|ch| *ch == '`')
+        .count()
+    })
+    .max()
+    .unwrap_or(0);
+  let marker = "`".repeat(longest.max(2) + 1);
+
+  let language = match node.attribute("language") {
+    Some(Value::String(language)) => language.as_ref(),
+    _ => "",
+  };
+
+  let info = format!("{}{}", language, annotation(node));
+  let content = content.strip_suffix('\n').unwrap_or(content);
+  if content.is_empty() {
+    format!("{}{}\n{}", marker, info.trim_start(), marker)
+  } else {
+    format!("{}{}\n{}\n{}", marker, info.trim_start(), content, marker)
+  }
+}
+
+fn list(node: &Node) -> String {
+  let ordered = node.attribute("ordered") == Some(&Value::Boolean(true));
+  let start = match node.attribute("number") {
+    Some(Value::Number(number)) => *number as usize,
+    _ => 1,
+  };
+
+  // Loose lists wrap their items in paragraphs and keep a blank line between them
+  let loose = children(node).any(|item| children(&item).any(|x| x.kind == Type::Paragraph));
+  let separator = if loose { "\n\n" } else { "\n" };
+
+  children(node)
+    .enumerate()
+    .map(|(index, item)| {
+      let marker = if ordered {
+        format!("{}. ", start + index)
+      } else {
+        "- ".into()
+      };
+
+      let content = inline_block(&item, separator);
+      indent(&content, &marker, &" ".repeat(marker.len()))
+    })
+    .collect::<Vec<_>>()
+    .join(separator)
+}
+
+fn table(node: &Node) -> String {
+  let rows: Vec<Vec<String>> = children(node)
+    .flat_map(|section| {
+      children(&section)
+        .map(|row| {
+          children(&row)
+            .map(|cell| inline_block(&cell, " ").replace('|', "\\|"))
+            .collect()
+        })
+        .collect::<Vec<_>>()
+    })
+    .collect();
+
+  let columns = rows.first().map_or(0, Vec::len);
+  let mut lines: Vec<String> = rows
+    .iter()
+    .map(|row| format!("| {} |", row.join(" | ")))
+    .collect();
+
+  if !lines.is_empty() {
+    lines.insert(1, format!("|{}", " --- |".repeat(columns)));
+  }
+
+  lines.join("\n")
+}
+
+fn escape(text: &str) -> String {
+  let mut output = String::with_capacity(text.len());
+  for ch in text.chars() {
+    if matches!(ch, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '&') {
+      output.push('\\');
+    }
+    output.push(ch);
+  }
+  output
+}
+
+fn code(content: &str) -> String {
+  let mut ticks = 1;
+  while content.contains(&"`".repeat(ticks)) {
+    ticks += 1;
+  }
+
+  let marker = "`".repeat(ticks);
+  if content.starts_with('`') || content.ends_with('`') {
+    format!("{} {} {}", marker, content, marker)
+  } else {
+    format!("{}{}{}", marker, content, marker)
+  }
+}
+
+fn destination(node: &Node, key: &str) -> String {
+  let link = match node.attribute(key) {
+    Some(Value::String(link)) => link.as_ref(),
+    _ => "",
+  };
+
+  let link = if link.is_empty() || link.contains([' ', '(', ')']) {
+    format!("<{}>", link)
+  } else {
+    link.to_string()
+  };
+
+  match node.attribute("title") {
+    Some(Value::String(title)) => format!("{} \"{}\"", link, title.replace('"', "\\\"")),
+    _ => link,
+  }
+}
+
+fn inline_children(node: &Node) -> String {
+  let mut output = String::new();
+  for child in children(node) {
+    push_inline(&mut output, &child);
+  }
+  output
+}
+
+// A `!` left right before a link would turn it into an image
+fn push_inline(output: &mut String, node: &Node) {
+  if node.kind == Type::Link && output.ends_with('!') {
+    output.insert(output.len() - 1, '\\');
+  }
+  output.push_str(&inline(node));
+}
+
+// Text that starts a line would otherwise be read as a heading, list item,
+// quote, fence or setext underline, so its leading marker is escaped
+fn lines(node: &Node) -> String {
+  let mut output = String::new();
+  // Escapes split text into separate nodes, so the text leading a line is
+  // collected before it's checked
+  let mut leading = Some(String::new());
+
+  for child in children(node) {
+    match &mut leading {
+      Some(text) if child.kind == Type::Text => text.push_str(&inline(&child)),
+      _ => {
+        if let Some(text) = leading.take() {
+          output.push_str(&escape_marker(&text));
+        }
+        push_inline(&mut output, &child);
+      }
+    }
+
+    if matches!(child.kind, Type::SoftBreak | Type::HardBreak) {
+      leading = Some(String::new());
+    }
+  }
+
+  if let Some(text) = leading {
+    output.push_str(&escape_marker(&text));
+  }
+
+  output
+}
+
+fn escape_marker(text: &str) -> String {
+  let digits = text.chars().take_while(char::is_ascii_digit).count();
+  let rest = &text[digits..];
+
+  if (1..10).contains(&digits)
+    && rest.starts_with(['.', ')'])
+    && (rest.len() == 1 || rest[1..].starts_with(char::is_whitespace))
+  {
+    format!("{}\\{}", &text[..digits], rest)
+  } else if text.starts_with(['#', '>', '-', '+', '=', '|', '~']) {
+    format!("\\{}", text)
+  } else {
+    text.to_string()
+  }
+}
+
+fn inline(node: &Node) -> String {
+  match node.kind {
+    Type::Text => match node.attribute("content") {
+      Some(Value::String(text)) => escape(text),
+      Some(Value::Expression(_, expression)) => format!("{{% {} %}}", self::expression(expression)),
+      _ => String::new(),
+    },
+    Type::Code => match node.attribute("content") {
+      Some(Value::String(content)) => code(content),
+      _ => String::new(),
+    },
+    Type::SoftBreak => "\n".into(),
+    Type::HardBreak => "\\\n".into(),
+    Type::Emphasis => format!("*{}*", inline_children(node)),
+    Type::Strong => format!("**{}**", inline_children(node)),
+    Type::Strike => format!("~~{}~~", inline_children(node)),
+    Type::Link => format!("[{}]({})", inline_children(node), destination(node, "href")),
+    Type::Image => format!("![{}]({})", inline_children(node), destination(node, "src")),
+    Type::Tag(_) if self_closing(node) => format!("{} /%}}", open_tag(node)),
+    Type::Tag(_) => format!(
+      "{} %}}{}{{% /{} %}}",
+      open_tag(node),
+      inline_children(node),
+      node.tag.as_deref().unwrap_or_default()
+    ),
+    Type::Inline => lines(node),
+    _ => inline_children(node),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::parse::parse;
+  use crate::tag::{self, Tag};
+
+  // Locations move when the source is reformatted, so they're left out
+  fn strip_locations(mut json: serde_json::Value) -> serde_json::Value {
+    if let Some(object) = json.as_object_mut() {
+      object.remove("location");
+    }
+
+    match json {
+      serde_json::Value::Object(object) => object
+        .into_iter()
+        .map(|(key, value)| (key, strip_locations(value)))
+        .collect(),
+      serde_json::Value::Array(items) => items.into_iter().map(strip_locations).collect(),
+      value => value,
+    }
+  }
+
+  fn ast(input: &str) -> serde_json::Value {
+    strip_locations(serde_json::to_value(&*parse(input).borrow()).unwrap())
+  }
+
+  fn assert_canonical(input: &str) -> String {
+    let output = format(&parse(input).borrow()).unwrap();
+    assert_eq!(ast(&output), ast(input));
+    assert_eq!(format(&parse(&output).borrow()).as_ref(), Some(&output));
+    output
+  }
+
+  #[test]
+  fn formats_markdown() {
+    let output = assert_canonical(
+      "# Heading\n\nSome *emphasis*, __strong__ and ~~strike~~ with `code`\nacross lines.\n\n* One\n* Two\n    1. Three\n    2. Four\n\n> Quoted\n> text\n\n---\n\n[Link](/path \"Title\") and ![Image](/img.png)\n",
+    );
+
+    assert_eq!(
+      output,
+      "# Heading\n\nSome *emphasis*, **strong** and ~~strike~~ with `code`\nacross lines.\n\n- One\n- Two\n  1. Three\n  2. Four\n\n> Quoted\n> text\n\n---\n\n[Link](/path \"Title\") and ![Image](/img.png)\n"
+    );
+  }
+
+  #[test]
+  fn formats_tags() {
+    let output = assert_canonical(
+      "{% callout   type=\"note\" .wide .dark #intro count=2 %}\nHello {% $user.name %} from {% default($place, \"home\", fallback=true) %}\n{% /callout %}\n\n{% image src=\"/a.png\" /%}\n\n# Title {% #title .big %}\n\nSee {% badge \"new\" %}this{% /badge %} too\n",
+    );
+
+    assert_eq!(
+      output,
+      "{% callout type=\"note\" .wide .dark #intro count=2 %}\nHello {% $user.name %} from {% default($place, \"home\", fallback=true) %}\n{% /callout %}\n\n{% image src=\"/a.png\" /%}\n\n# Title {% #title .big %}\n\nSee {% badge \"new\" %}this{% /badge %} too\n"
+    );
+  }
+
+  #[test]
+  fn formats_tables_and_fences() {
+    let output = assert_canonical(
+      "---\ntitle: Example\n---\n\n|a|b|\n|-|-|\n|c|d|\n\n```js {% .code process=false %}\nconsole.log(\"```\");\n```\n",
+    );
+
+    assert_eq!(
+      output,
+      "---\ntitle: Example\n---\n\n| a | b |\n| --- | --- |\n| c | d |\n\n```js {% .code process=false %}\nconsole.log(\"```\");\n```\n"
+    );
+  }
+
+  #[test]
+  fn escapes_block_markers() {
+    let input = "\\# Not a heading\n\n1986\\. A great year\n\n\\- Not a list\n\\+ Nor this\n\\> Or a quote\nText\n\\===\nText\n\\---\n\n\\~~~\n\n- \\- Item\n";
+    assert_eq!(assert_canonical(input), input);
+
+    let output = assert_canonical("Not a heading #1 or a list - 2. either\n");
+    assert_eq!(output, "Not a heading #1 or a list - 2. either\n");
+
+    let input = "Fish \\& chips, \\&copy; and \\&amp;copy;\n\n\\![Not an image](/a.png) or *this\\![one](/b.png)*\n";
+    assert_eq!(assert_canonical(input), input);
+
+    let output = format(&parse("Entities &amp;copy; and &copy;\n").borrow());
+    assert_eq!(output.as_deref(), Some("Entities \\&copy; and ©\n"));
+  }
+
+  #[test]
+  fn escapes_table_pipes() {
+    let input = "| a | b |\n| --- | --- |\n| c \\| d | e |\n";
+    assert_eq!(assert_canonical(input), input);
+  }
+
+  #[test]
+  fn keeps_annotations_named_like_syntax() {
+    let input = "Paragraph {% title=\"x\" level=2 %}\n\n# Heading {% title=\"y\" %}\n\n```js {% title=\"z\" %}\ncode\n```\n\n{% callout title=\"w\" content=\"v\" %}\nText\n{% /callout %}\n";
+    assert_eq!(assert_canonical(input), input);
+  }
+
+  #[test]
+  fn escapes_tag_strings() {
+    let input = "{% badge title=\"a \\\\\\\"quoted\\\\\\\"\" /%}\n";
+    assert_eq!(assert_canonical(input), input);
+
+    let doc = parse(input);
+    let badge = doc.borrow().children.as_ref().unwrap()[0].clone();
+    assert_eq!(
+      badge.borrow().attribute("title"),
+      Some(&Value::from("a \\\"quoted\\\""))
+    );
+  }
+
+  #[test]
+  fn refuses_errors() {
+    for input in [
+      "{% /bar %}\n",
+      "{% unclosed %}\nText\n",
+      "Text {% foo \"unterminated %} here\n",
+    ] {
+      assert_eq!(format(&parse(input).borrow()), None, "{}", input);
+    }
+  }
+
+  #[test]
+  fn formats_values() {
+    let input = "{% foo $a.b[0][\"c d\"][@e] items=[1, -2.5, \"t\\\"x\"] data={a: true, \"b c\": null} f=f(1, x=g()) %}";
+    let attrs = match tag::parse(input) {
+      Tag::Open(_, Some(attrs)) => attrs,
+      tag => panic!("expected a tag, got {:?}", tag),
+    };
+
+    let attrs = attributes(attrs.iter().map(|(name, attr)| (name.as_ref(), attr)));
+    assert_eq!(format!("{{% foo {} %}}", attrs.join(" ")), input);
+  }
+}
@@ -0,0 +1,214 @@
+use crate::model::{
+  location::{LineIndex, Location},
+  node::{Error, ErrorLevel, FrontmatterKind},
+  value::Value,
+  Attributes,
+};
+use std::ops::Range;
+
+fn convert_yaml<'a>(value: serde_yaml::Value) -> Value<'a> {
+  match value {
+    serde_yaml::Value::Null => Value::Null,
+    serde_yaml::Value::Bool(value) => Value::Boolean(value),
+    serde_yaml::Value::Number(value) => value.as_f64().map_or(Value::Null, Value::Number),
+    serde_yaml::Value::String(value) => value.into(),
+    serde_yaml::Value::Sequence(values) => {
+      Value::Array(values.into_iter().map(convert_yaml).collect())
+    }
+    serde_yaml::Value::Mapping(values) => Value::Hash(
+      values
+        .into_iter()
+        .filter_map(|(key, value)| {
+          let key = match key {
+            serde_yaml::Value::String(key) => key,
+            serde_yaml::Value::Number(key) => key.to_string(),
+            serde_yaml::Value::Bool(key) => key.to_string(),
+            _ => return None,
+          };
+
+          Some((key.into(), convert_yaml(value)))
+        })
+        .collect(),
+    ),
+  }
+}
+
+fn convert_toml<'a>(value: toml::Value) -> Value<'a> {
+  match value {
+    toml::Value::String(value) => value.into(),
+    toml::Value::Integer(value) => Value::Number(value as f64),
+    toml::Value::Float(value) => Value::Number(value),
+    toml::Value::Boolean(value) => Value::Boolean(value),
+    toml::Value::Datetime(value) => value.to_string().into(),
+    toml::Value::Array(values) => Value::Array(values.into_iter().map(convert_toml).collect()),
+    toml::Value::Table(values) => Value::Hash(
+      values
+        .into_iter()
+        .map(|(key, value)| (key.into(), convert_toml(value)))
+        .collect(),
+    ),
+  }
+}
+
+fn convert_json<'a>(value: serde_json::Value) -> Value<'a> {
+  match value {
+    serde_json::Value::Null => Value::Null,
+    serde_json::Value::Bool(value) => Value::Boolean(value),
+    serde_json::Value::Number(value) => value.as_f64().map_or(Value::Null, Value::Number),
+    serde_json::Value::String(value) => value.into(),
+    serde_json::Value::Array(values) => {
+      Value::Array(values.into_iter().map(convert_json).collect())
+    }
+    serde_json::Value::Object(values) => Value::Hash(
+      values
+        .into_iter()
+        .map(|(key, value)| (key.into(), convert_json(value)))
+        .collect(),
+    ),
+  }
+}
+
+// Converts a zero-based line and column into a byte offset within the source
+fn line_offset(source: &str, line: usize, column: usize) -> usize {
+  let start: usize = source.split_inclusive('\n').take(line).map(str::len).sum();
+  (start + column).min(source.len())
+}
+
+fn invalid(message: String, location: Location) -> Error {
+  Error {
+    id: "invalid-frontmatter",
+    level: ErrorLevel::Error,
+    message,
+    location: Some(location),
+  }
+}
+
+fn into_attributes<'a>(value: Value<'a>, location: Location) -> Result<Attributes<'a>, Error> {
+  match value {
+    Value::Null => Ok(Attributes::new()),
+    Value::Hash(attributes) => Ok(attributes),
+    _ => Err(invalid("Frontmatter must be a map".into(), location)),
+  }
+}
+
+pub fn parse<'a>(
+  kind: FrontmatterKind,
+  source: &str,
+  location: Range<usize>,
+  index: &LineIndex,
+) -> Result<Attributes<'a>, Error> {
+  let value = match kind {
+    FrontmatterKind::Yaml => serde_yaml::from_str(source)
+      .map(convert_yaml)
+      .map_err(|err| (err.to_string(), err.location().map(|x| x.index()))),
+
+    FrontmatterKind::Toml => toml::from_str(source).map(convert_toml).map_err(|err| {
+      let index = err
+        .line_col()
+        .map(|(line, col)| line_offset(source, line, col));
+      (err.to_string(), index)
+    }),
+
+    FrontmatterKind::Json if source.trim().is_empty() => Ok(Value::Null),
+
+    FrontmatterKind::Json => serde_json::from_str(source)
+      .map(convert_json)
+      .map_err(|err| {
+        let (line, col) = (err.line().saturating_sub(1), err.column().saturating_sub(1));
+        (err.to_string(), Some(line_offset(source, line, col)))
+      }),
+  };
+
+  match value {
+    Ok(value) => into_attributes(value, index.location(location)),
+    Err((message, offset)) => {
+      let location = match offset {
+        Some(offset) => index.location(location.start + offset..location.start + offset),
+        None => index.location(location),
+      };
+
+      Err(invalid(
+        format!("Invalid frontmatter: {}", message),
+        location,
+      ))
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse_source<'a>(kind: FrontmatterKind, source: &str) -> Result<Attributes<'a>, Error> {
+    let input = format!("---\n{}---\n", source);
+    parse(kind, source, 4..4 + source.len(), &LineIndex::new(&input))
+  }
+
+  #[test]
+  fn parse_nested_yaml() {
+    let source = "title: Example\ntags:\n  - one\n  - two\nmeta:\n  draft: true\n  version: 2\n";
+    assert_eq!(
+      parse_source(FrontmatterKind::Yaml, source),
+      Ok(mdattrs!(
+        title = "Example",
+        tags = vec!["one".into(), "two".into()],
+        meta = mdattrs!(draft = true, version = 2)
+      ))
+    );
+  }
+
+  #[test]
+  fn parse_malformed_yaml() {
+    let source = "title: Example\ntags: [one, two\n";
+    let error = parse_source(FrontmatterKind::Yaml, source).unwrap_err();
+    assert_eq!(error.id, "invalid-frontmatter");
+    assert!(matches!(error.location, Some(location) if location.start.line > 1));
+  }
+
+  #[test]
+  fn parse_non_map_yaml() {
+    let error = parse_source(FrontmatterKind::Yaml, "- one\n- two\n").unwrap_err();
+    assert_eq!(error.message, "Frontmatter must be a map");
+    assert_eq!(error.location.map(|x| x.range()), Some(4..16));
+  }
+
+  #[test]
+  fn parse_toml() {
+    let source = "title = \"Example\"\n\n[meta]\ndraft = true\nversion = 2\n";
+    assert_eq!(
+      parse_source(FrontmatterKind::Toml, source),
+      Ok(mdattrs!(
+        title = "Example",
+        meta = mdattrs!(draft = true, version = 2)
+      ))
+    );
+  }
+
+  #[test]
+  fn parse_malformed_toml() {
+    let source = "title = \"Example\"\ndraft = \n";
+    let error = parse_source(FrontmatterKind::Toml, source).unwrap_err();
+    let location = error.location.unwrap();
+    assert_eq!(location.range(), 30..30);
+    assert_eq!((location.start.line, location.start.character), (2, 8));
+  }
+
+  #[test]
+  fn parse_json() {
+    let source = "{\"title\": \"Example\", \"tags\": [\"one\", \"two\"]}\n";
+    assert_eq!(
+      parse_source(FrontmatterKind::Json, source),
+      Ok(mdattrs!(
+        title = "Example",
+        tags = vec!["one".into(), "two".into()]
+      ))
+    );
+  }
+
+  #[test]
+  fn parse_malformed_json() {
+    let source = "{\n  \"title\": Example\n}\n";
+    let error = parse_source(FrontmatterKind::Json, source).unwrap_err();
+    assert_eq!(error.location.map(|x| x.range()), Some(17..17));
+  }
+}
@@ -0,0 +1,172 @@
+use crate::model::{
+  schema::{evaluate_fn, Config, EvaluateFn},
+  value::Value,
+  Attributes,
+};
+
+// A missing value is as falsy as an undefined one
+pub fn condition(value: Option<&Value>) -> bool {
+  value.is_some_and(Value::truthy)
+}
+
+fn parameter<'a>(parameters: &Attributes<'a>, index: usize) -> Value<'a> {
+  parameters
+    .get(index.to_string().as_str())
+    .map_or(Value::Undefined, |value| value.resolved(Value::clone))
+}
+
+fn equals<'a>(parameters: &Attributes<'a>, _config: &'a Config<'a>) -> Value<'a> {
+  let mut values = parameters
+    .values()
+    .map(|value| value.resolved(Value::clone));
+  let first = values.next();
+  Value::Boolean(values.all(|value| Some(value) == first))
+}
+
+fn and<'a>(parameters: &Attributes<'a>, _config: &'a Config<'a>) -> Value<'a> {
+  Value::Boolean(parameters.values().all(|value| condition(Some(value))))
+}
+
+fn or<'a>(parameters: &Attributes<'a>, _config: &'a Config<'a>) -> Value<'a> {
+  Value::Boolean(parameters.values().any(|value| condition(Some(value))))
+}
+
+fn not<'a>(parameters: &Attributes<'a>, _config: &'a Config<'a>) -> Value<'a> {
+  Value::Boolean(!condition(Some(&parameter(parameters, 0))))
+}
+
+fn default<'a>(parameters: &Attributes<'a>, _config: &'a Config<'a>) -> Value<'a> {
+  match parameter(parameters, 0) {
+    Value::Undefined => parameter(parameters, 1),
+    value => value,
+  }
+}
+
+fn debug<'a>(parameters: &Attributes<'a>, _config: &'a Config<'a>) -> Value<'a> {
+  match parameter(parameters, 0) {
+    Value::Undefined => Value::Undefined,
+    value => serde_json::to_string_pretty(&value).map_or(Value::Undefined, Value::from),
+  }
+}
+
+pub fn builtin(name: &str) -> Option<EvaluateFn> {
+  match name {
+    "equals" => Some(evaluate_fn(equals)),
+    "and" => Some(evaluate_fn(and)),
+    "or" => Some(evaluate_fn(or)),
+    "not" => Some(evaluate_fn(not)),
+    "default" => Some(evaluate_fn(default)),
+    "debug" => Some(evaluate_fn(debug)),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::model::schema::{FunctionSchema, Variables};
+  use crate::resolve::resolve;
+  use crate::tag::{parse, Tag};
+
+  fn config<'a>() -> Config<'a> {
+    Config {
+      variables: Variables::Values(mdattrs!(plan = "pro", seats = 0, admin = false)).into(),
+      ..Default::default()
+    }
+  }
+
+  fn evaluate<'a>(input: &'a str, config: &'a Config<'a>) -> Value<'a> {
+    match parse(input) {
+      Tag::Value(value) => {
+        resolve(&value, config).expect("resolves");
+        value.resolved(Value::clone)
+      }
+      tag => panic!("expected a value, got {:?}", tag),
+    }
+  }
+
+  #[test]
+  fn equals_function() {
+    let config = config();
+    assert_eq!(
+      evaluate("{% equals($plan, \"pro\") %}", &config),
+      Value::Boolean(true)
+    );
+    assert_eq!(
+      evaluate("{% equals($plan, \"free\") %}", &config),
+      Value::Boolean(false)
+    );
+    assert_eq!(
+      evaluate("{% equals(1, 1, 1) %}", &config),
+      Value::Boolean(true)
+    );
+  }
+
+  #[test]
+  fn logical_functions() {
+    let config = config();
+    assert_eq!(
+      evaluate("{% and($plan, $seats) %}", &config),
+      Value::Boolean(true)
+    );
+    assert_eq!(
+      evaluate("{% and($plan, $admin) %}", &config),
+      Value::Boolean(false)
+    );
+    assert_eq!(
+      evaluate("{% or($admin, $missing) %}", &config),
+      Value::Boolean(false)
+    );
+    assert_eq!(
+      evaluate("{% or($admin, $plan) %}", &config),
+      Value::Boolean(true)
+    );
+    assert_eq!(evaluate("{% not($admin) %}", &config), Value::Boolean(true));
+    assert_eq!(evaluate("{% not($plan) %}", &config), Value::Boolean(false));
+    assert_eq!(
+      evaluate("{% not(equals($plan, \"free\")) %}", &config),
+      Value::Boolean(true)
+    );
+  }
+
+  #[test]
+  fn default_function() {
+    let config = config();
+    assert_eq!(
+      evaluate("{% default($missing, 2) %}", &config),
+      Value::Number(2.0)
+    );
+    assert_eq!(
+      evaluate("{% default($seats, 2) %}", &config),
+      Value::Number(0.0)
+    );
+  }
+
+  #[test]
+  fn debug_function() {
+    let config = config();
+    assert_eq!(
+      evaluate("{% debug([\"one\", \"two\"]) %}", &config),
+      Value::from("[\n  \"one\",\n  \"two\"\n]")
+    );
+  }
+
+  #[test]
+  fn builtins_can_be_overridden() {
+    let config = Config {
+      functions: hash!(
+        "equals" => FunctionSchema {
+          attributes: None,
+          evaluate: evaluate_fn(|_parameters, _config| "overridden".into()),
+        }
+      )
+      .into(),
+      ..config()
+    };
+
+    assert_eq!(
+      evaluate("{% equals(1, 1) %}", &config),
+      Value::from("overridden")
+    );
+  }
+}
@@ -4,11 +4,16 @@ extern crate pest;
 
 #[macro_use]
 pub mod macros;
+pub mod format;
+pub mod frontmatter;
+pub mod functions;
 pub mod model;
 pub mod parse;
+pub mod partial;
 pub mod render;
 pub mod resolve;
 pub mod schema;
 pub mod tag;
 pub mod tokenize;
 pub mod transform;
+pub mod validate;
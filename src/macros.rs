@@ -14,10 +14,17 @@ macro_rules! mdattrs {
 }
 
 macro_rules! mdnode {
+  ($kind:expr) => {
+    $crate::model::node::NodeRef::from($crate::model::node::Node {
+      kind: $kind,
+      ..$crate::model::node::Node::default()
+    })
+  };
+
   ($kind:expr, $range:expr $(,$($key:ident = $value:expr),*)?) => {
     $crate::model::node::NodeRef::from($crate::model::node::Node {
       kind: $kind,
-      location: $range.into(),
+      location: Some($range),
       $(attributes: Some($crate::model::Attributes::from([
         $((stringify!($key).into(), $crate::model::value::Value::from($value)),)+
       ])),)?
@@ -28,7 +35,7 @@ macro_rules! mdnode {
   ($kind:expr, $range:expr, $attrs:expr) => {
     $crate::model::node::NodeRef::from($crate::model::node::Node {
       kind: $kind,
-      location: $range.into(),
+      location: Some($range),
       attributes: $attrs.into(),
       ..$crate::model::node::Node::default()
     })
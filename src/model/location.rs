@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+// Zero-based line and character, matching the JS Markdoc location object
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub struct Position {
+  pub line: usize,
+  #[serde(default)]
+  pub character: usize,
+  #[serde(default)]
+  pub offset: usize,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub struct Location {
+  // Set on nodes grafted in from a partial, relative to the partial provider
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub file: Option<String>,
+  pub start: Position,
+  pub end: Position,
+}
+
+impl Location {
+  pub fn range(&self) -> Range<usize> {
+    self.start.offset..self.end.offset
+  }
+}
+
+pub struct LineIndex<'a> {
+  input: &'a str,
+  lines: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+  pub fn new(input: &'a str) -> Self {
+    let lines = std::iter::once(0)
+      .chain(input.match_indices('\n').map(|(index, _)| index + 1))
+      .collect();
+
+    LineIndex { input, lines }
+  }
+
+  pub fn position(&self, offset: usize) -> Position {
+    let offset = offset.min(self.input.len());
+    let line = self.lines.partition_point(|&start| start <= offset) - 1;
+    let start = self.lines[line];
+
+    let character = match self.input.get(start..offset) {
+      Some(text) => text.chars().count(),
+      None => offset - start,
+    };
+
+    Position {
+      line,
+      character,
+      offset,
+    }
+  }
+
+  pub fn location(&self, range: Range<usize>) -> Location {
+    Location {
+      file: None,
+      start: self.position(range.start),
+      end: self.position(range.end),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn positions() {
+    let index = LineIndex::new("one\ntwö\n\nfour");
+    let position = |offset| {
+      let position = index.position(offset);
+      (position.line, position.character, position.offset)
+    };
+
+    assert_eq!(position(0), (0, 0, 0));
+    assert_eq!(position(3), (0, 3, 3));
+    assert_eq!(position(4), (1, 0, 4));
+    assert_eq!(position(8), (1, 3, 8));
+    assert_eq!(position(9), (2, 0, 9));
+    assert_eq!(position(14), (3, 4, 14));
+  }
+
+  #[test]
+  fn location_range() {
+    let index = LineIndex::new("# Heading\n\nParagraph\n");
+    let location = index.location(11..20);
+    assert_eq!(location.start.line, 2);
+    assert_eq!(location.end.character, 9);
+    assert_eq!(location.range(), 11..20);
+  }
+}
@@ -1,9 +1,10 @@
+use indexmap::IndexMap;
 use pulldown_cmark::CowStr;
-use std::collections::HashMap;
 
+pub mod location;
 pub mod node;
 pub mod schema;
 pub mod value;
 pub mod render;
 
-pub type Attributes<'a> = HashMap<CowStr<'a>, value::Value<'a>>;
\ No newline at end of file
+pub type Attributes<'a> = IndexMap<CowStr<'a>, value::Value<'a>>;
\ No newline at end of file
@@ -1,11 +1,11 @@
+use super::location::Location;
 use super::value::Value;
 use super::Attributes;
 use pulldown_cmark::CowStr;
+use serde::de::IntoDeserializer;
 use serde::ser::SerializeStruct;
-use serde::{Deserialize, Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::ops::Range;
 use std::rc::Rc;
 
 #[derive(Clone, Hash, Eq, PartialEq, Debug, Serialize, Deserialize)]
@@ -33,6 +33,7 @@ pub enum Type {
   #[serde(rename = "em")]
   Emphasis,
   Strong,
+  #[serde(alias = "s")]
   Strike,
   Link,
   Image,
@@ -69,7 +70,7 @@ impl Type {
   }
 }
 
-#[derive(PartialEq, Debug, Serialize)]
+#[derive(PartialEq, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ErrorLevel {
   Debug,
@@ -84,7 +85,71 @@ pub struct Error {
   pub id: &'static str,
   pub level: ErrorLevel,
   pub message: String,
-  pub location: Option<Range<usize>>,
+  pub location: Option<Location>,
+}
+
+// Ids are static, so reading one back maps it onto the ids reported here
+static ERROR_IDS: &[&str] = &[
+  "attribute-missing-required",
+  "attribute-type-invalid",
+  "attribute-undefined",
+  "invalid-frontmatter",
+  "missing-closing",
+  "missing-opening",
+  "parameter-count-invalid",
+  "parameter-missing-required",
+  "parameter-type-invalid",
+  "parameter-undefined",
+  "partial-cycle",
+  "partial-file-invalid",
+  "partial-not-found",
+  "self-closing-children",
+  "syntax-error",
+  "undefined-function",
+  "undefined-tag",
+  "undefined-variable",
+];
+
+#[derive(Deserialize)]
+struct ErrorData {
+  id: String,
+  level: ErrorLevel,
+  message: String,
+  location: Option<Location>,
+}
+
+impl<'de> Deserialize<'de> for Error {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    use serde::de::Error as _;
+
+    let data = ErrorData::deserialize(deserializer)?;
+    match ERROR_IDS.iter().find(|id| **id == data.id) {
+      Some(id) => Ok(Error {
+        id,
+        level: data.level,
+        message: data.message,
+        location: data.location,
+      }),
+      None => Err(D::Error::unknown_variant(&data.id, ERROR_IDS)),
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrontmatterKind {
+  Yaml,
+  Toml,
+  Json,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Frontmatter {
+  pub kind: FrontmatterKind,
+  pub location: Location,
 }
 
 #[derive(PartialEq, Debug, Default)]
@@ -93,8 +158,9 @@ pub struct Node<'a> {
   pub tag: Option<CowStr<'a>>,
   pub attributes: Option<Attributes<'a>>,
   pub children: Option<Vec<NodeRef<'a>>>,
-  pub location: Option<Range<usize>>,
+  pub location: Option<Location>,
   pub errors: Option<Vec<Error>>,
+  pub frontmatter_source: Option<Frontmatter>,
 }
 
 impl<'a> Node<'a> {
@@ -106,11 +172,26 @@ impl<'a> Node<'a> {
     }
   }
 
+  pub fn push_error(&mut self, error: Error) {
+    if let Some(errors) = &mut self.errors {
+      errors.push(error);
+    } else {
+      self.errors = Some(vec![error]);
+    }
+  }
+
   pub fn attribute(&self, key: &str) -> Option<&Value<'a>> {
     self.attributes.as_ref().and_then(|x| x.get(key))
   }
 
-  pub fn set_attributes(&mut self, attrs: HashMap<CowStr<'a>, Value<'a>>) {
+  pub fn frontmatter(&self) -> Option<&Attributes<'a>> {
+    match self.attribute("frontmatter") {
+      Some(Value::Hash(frontmatter)) => Some(frontmatter),
+      _ => None,
+    }
+  }
+
+  pub fn set_attributes(&mut self, attrs: Attributes<'a>) {
     if let Some(attributes) = &mut self.attributes {
       attributes.extend(attrs)
     } else {
@@ -147,6 +228,10 @@ impl<'a> Serialize for Node<'a> {
       field_count += 1;
     }
 
+    if self.frontmatter_source.is_some() {
+      field_count += 1;
+    }
+
     let mut state = serializer.serialize_struct("Node", field_count)?;
     state.serialize_field("$$mdtype", "Node")?;
     state.serialize_field("inline", &self.kind.is_inline())?;
@@ -177,6 +262,256 @@ impl<'a> Serialize for Node<'a> {
       state.serialize_field("attributes", &Attributes::new())?;
     }
 
+    if let Some(source) = &self.frontmatter_source {
+      state.serialize_field("frontmatterSource", source)?;
+    }
+
     state.end()
   }
 }
+
+// Mirrors the serialized layout, with the fields JS Markdoc adds (`lines`,
+// `slots`, and so on) ignored and empty collections read back as `None`
+#[derive(Deserialize)]
+struct NodeData<'a> {
+  #[serde(default)]
+  inline: bool,
+  #[serde(default)]
+  location: Option<Location>,
+  #[serde(rename = "type", borrow)]
+  kind: Value<'a>,
+  #[serde(default, borrow)]
+  tag: Option<Value<'a>>,
+  #[serde(default, borrow)]
+  children: Vec<NodeRef<'a>>,
+  #[serde(default)]
+  errors: Vec<Error>,
+  #[serde(default, borrow)]
+  attributes: Option<Value<'a>>,
+  #[serde(default, rename = "frontmatterSource")]
+  frontmatter_source: Option<Frontmatter>,
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Node<'a> {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    use serde::de::Error as _;
+
+    let data = NodeData::deserialize(deserializer)?;
+    let kind = match data.kind {
+      Value::String(kind) if kind.as_ref() == "tag" => Type::Tag(data.inline),
+      Value::String(kind) => Type::deserialize(IntoDeserializer::<D::Error>::into_deserializer(
+        kind.as_ref(),
+      ))?,
+      _ => return Err(D::Error::custom("node type must be a string")),
+    };
+
+    let tag = match data.tag {
+      Some(Value::String(tag)) => Some(tag),
+      None | Some(Value::Null) => None,
+      _ => return Err(D::Error::custom("node tag must be a string")),
+    };
+
+    let attributes = match data.attributes {
+      Some(Value::Hash(attrs)) if !attrs.is_empty() => Some(attrs),
+      Some(Value::Hash(_) | Value::Null) | None => None,
+      _ => return Err(D::Error::custom("node attributes must be an object")),
+    };
+
+    Ok(Node {
+      kind,
+      tag,
+      attributes,
+      children: Some(data.children).filter(|children| !children.is_empty()),
+      location: data.location,
+      errors: Some(data.errors).filter(|errors| !errors.is_empty()),
+      frontmatter_source: data.frontmatter_source,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::model::value::Expression;
+  use crate::parse::parse;
+
+  #[test]
+  fn json_round_trip() {
+    let input = r#"---
+title: "Round trip"
+---
+
+# Hello {% $user.name %} {% .intro #lead %}
+
+{% callout type="note" level=2 flag=true items=[1, "two"] %}
+Say **"hi"** to {% default($name, "friend") %} and [a link](/path "Title").
+{% /callout %}
+
+{% image src="/a.png" /%}
+
+- one
+- ~~two~~
+
+| a | b |
+|---|---|
+| `c` | d |
+
+```js {% .code %}
+console.log("tabs\tand\nnewlines");
+```
+
+{% unclosed %}
+"#;
+
+    let doc = parse(input);
+    let json = serde_json::to_string(&*doc.borrow()).unwrap();
+    assert!(json.contains(r#""frontmatterSource":{"kind":"yaml""#));
+    let node: Node = serde_json::from_str(&json).unwrap();
+    assert_eq!(node, *doc.borrow());
+    assert_eq!(serde_json::to_string(&node).unwrap(), json);
+  }
+
+  #[test]
+  fn json_round_trip_mdtype_hashes() {
+    let doc = parse("{% foo /%}");
+    let foo = doc.borrow().children.as_ref().unwrap()[0].clone();
+    foo.borrow_mut().attributes = Some(Attributes::from([
+      (
+        "variable".into(),
+        Value::Hash(Attributes::from([
+          ("$$mdtype".into(), "Variable".into()),
+          ("path".into(), vec!["user".into()].into()),
+          ("label".into(), "User".into()),
+        ])),
+      ),
+      (
+        "function".into(),
+        Value::Hash(Attributes::from([("$$mdtype".into(), "Function".into())])),
+      ),
+      (
+        "sigil".into(),
+        Value::Hash(Attributes::from([
+          ("$$mdtype".into(), "Variable".into()),
+          ("sigil".into(), "%".into()),
+          ("path".into(), vec!["user".into()].into()),
+        ])),
+      ),
+    ]));
+
+    let json = serde_json::to_string(&*doc.borrow()).unwrap();
+    let node: Node = serde_json::from_str(&json).unwrap();
+    assert_eq!(node, *doc.borrow());
+  }
+
+  #[test]
+  fn json_round_trip_undefined() {
+    let doc = parse("{% foo /%}");
+    let foo = doc.borrow().children.as_ref().unwrap()[0].clone();
+    foo.borrow_mut().attributes = Some(Attributes::from([
+      ("missing".into(), Value::Undefined),
+      ("empty".into(), Value::Null),
+      (
+        "items".into(),
+        vec![Value::Undefined, Value::Null, 1.into()].into(),
+      ),
+    ]));
+
+    let json = serde_json::to_string(&*doc.borrow()).unwrap();
+    let node: Node = serde_json::from_str(&json).unwrap();
+    assert_eq!(node, *doc.borrow());
+    assert_eq!(serde_json::to_string(&node).unwrap(), json);
+  }
+
+  // Block nodes from the JS parser only carry lines in their locations, and
+  // include fields like `lines` and `slots` that have no counterpart here
+  #[test]
+  fn deserialize_js_ast() {
+    let json = include_str!("fixtures/js-ast.json");
+    let node: Node = serde_json::from_str(json).unwrap();
+    assert_eq!(node.kind, Type::Document);
+    assert_eq!(node.location.as_ref().map(|x| x.end.line), Some(9));
+
+    let children = node.children.as_ref().unwrap();
+    let heading = children[0].borrow();
+    assert_eq!(heading.attribute("level"), Some(&Value::Number(1.0)));
+
+    let inline = heading.children.as_ref().unwrap()[0].borrow();
+    assert_eq!(
+      inline.children.as_ref().unwrap()[1]
+        .borrow()
+        .attribute("content"),
+      Some(&Expression::Variable('$', vec!["user".into(), "name".into()]).into())
+    );
+
+    let paragraph = children[1].borrow();
+    let inline = paragraph.children.as_ref().unwrap()[0].borrow();
+    let kinds: Vec<_> = inline
+      .children
+      .iter()
+      .flatten()
+      .map(|child| child.borrow().kind.clone())
+      .collect();
+    assert_eq!(
+      kinds,
+      vec![
+        Type::Text,
+        Type::Strike,
+        Type::SoftBreak,
+        Type::Text,
+        Type::Text
+      ]
+    );
+
+    let callout = children[2].borrow();
+    assert_eq!(callout.kind, Type::Tag(false));
+    assert_eq!(callout.tag.as_deref(), Some("callout"));
+    assert_eq!(callout.attribute("type"), Some(&Value::from("note")));
+    let location = callout.location.clone().unwrap();
+    assert_eq!((location.start.line, location.start.character), (5, 0));
+    assert_eq!((location.end.line, location.end.character), (8, 0));
+  }
+
+  #[test]
+  fn serialize_functions_without_locations() {
+    let doc = parse("{% default($name, 1) %}");
+    let json = serde_json::to_value(&*doc.borrow()).unwrap();
+    assert_eq!(
+      json.pointer("/children/0/attributes/content"),
+      Some(&serde_json::json!({
+        "$$mdtype": "Function",
+        "name": "default",
+        "parameters": {
+          "0": {"$$mdtype": "Variable", "sigil": "$", "path": ["name"]},
+          "1": 1.0
+        }
+      }))
+    );
+  }
+
+  #[test]
+  fn deserialize_expressions() {
+    let value: Value = serde_json::from_str(
+      r#"{"$$mdtype":"Function","name":"equals","parameters":{"0":{"$$mdtype":"Variable","sigil":"@","path":["page",0]},"1":"x"}}"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+      value,
+      Expression::Function(
+        "equals".into(),
+        Attributes::from([
+          (
+            "0".into(),
+            Expression::Variable('@', vec!["page".into(), 0.into()]).into()
+          ),
+          ("1".into(), "x".into()),
+        ]),
+        None
+      )
+      .into()
+    );
+  }
+}
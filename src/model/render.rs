@@ -1,5 +1,11 @@
+use super::value::Value;
 use super::Attributes;
 use pulldown_cmark::CowStr;
+use serde::de::{self, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::ser::{SerializeMap, SerializeSeq};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::marker::PhantomData;
 
 #[derive(Debug)]
 pub enum Renderable<'a> {
@@ -12,3 +18,258 @@ pub enum Renderable<'a> {
   String(CowStr<'a>),
   Null,
 }
+
+// Render trees follow the JS Markdoc layout: tags are `$$mdtype: "Tag"`
+// objects, fragments are arrays, and attributes hold their resolved values
+impl<'a> Serialize for Renderable<'a> {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    match self {
+      Renderable::Tag {
+        name,
+        attributes,
+        children,
+      } => {
+        let mut map = serializer.serialize_map(Some(4))?;
+        map.serialize_entry("$$mdtype", "Tag")?;
+        map.serialize_entry("name", name.as_ref())?;
+        map.serialize_entry("attributes", &ResolvedAttributes(attributes.as_ref()))?;
+        map.serialize_entry("children", children.as_deref().unwrap_or_default())?;
+        map.end()
+      }
+      Renderable::Fragment(children) => children.serialize(serializer),
+      Renderable::String(value) => serializer.serialize_str(value),
+      Renderable::Null => serializer.serialize_unit(),
+    }
+  }
+}
+
+struct ResolvedAttributes<'b, 'a>(Option<&'b Attributes<'a>>);
+
+impl<'b, 'a> Serialize for ResolvedAttributes<'b, 'a> {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    let attrs = self.0.into_iter().flatten();
+    let mut map = serializer.serialize_map(self.0.map(Attributes::len))?;
+    for (key, value) in attrs {
+      map.serialize_entry(key.as_ref(), &Resolved(value))?;
+    }
+    map.end()
+  }
+}
+
+struct Resolved<'b, 'a>(&'b Value<'a>);
+
+impl<'b, 'a> Serialize for Resolved<'b, 'a> {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    match self.0 {
+      Value::Expression(value, _) => Resolved(&value.borrow()).serialize(serializer),
+      Value::Hash(attrs) => ResolvedAttributes(Some(attrs)).serialize(serializer),
+      Value::Array(items) => {
+        let mut seq = serializer.serialize_seq(Some(items.len()))?;
+        for item in items {
+          seq.serialize_element(&Resolved(item))?;
+        }
+        seq.end()
+      }
+      Value::Undefined => serializer.serialize_unit(),
+      value => value.serialize(serializer),
+    }
+  }
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Renderable<'a> {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    deserializer.deserialize_any(RenderableVisitor(PhantomData))
+  }
+}
+
+struct RenderableVisitor<'a>(PhantomData<Renderable<'a>>);
+
+impl<'de: 'a, 'a> Visitor<'de> for RenderableVisitor<'a> {
+  type Value = Renderable<'a>;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a Markdoc render tree")
+  }
+
+  fn visit_unit<E>(self) -> Result<Renderable<'a>, E> {
+    Ok(Renderable::Null)
+  }
+
+  fn visit_none<E>(self) -> Result<Renderable<'a>, E> {
+    Ok(Renderable::Null)
+  }
+
+  // JS render trees can carry scalar children, which only ever render as text
+  fn visit_bool<E>(self, value: bool) -> Result<Renderable<'a>, E> {
+    Ok(Renderable::String(value.to_string().into()))
+  }
+
+  fn visit_i64<E>(self, value: i64) -> Result<Renderable<'a>, E> {
+    Ok(Renderable::String(value.to_string().into()))
+  }
+
+  fn visit_u64<E>(self, value: u64) -> Result<Renderable<'a>, E> {
+    Ok(Renderable::String(value.to_string().into()))
+  }
+
+  fn visit_f64<E>(self, value: f64) -> Result<Renderable<'a>, E> {
+    Ok(Renderable::String(value.to_string().into()))
+  }
+
+  fn visit_borrowed_str<E>(self, value: &'de str) -> Result<Renderable<'a>, E> {
+    Ok(Renderable::String(CowStr::Borrowed(value)))
+  }
+
+  fn visit_str<E>(self, value: &str) -> Result<Renderable<'a>, E> {
+    Ok(Renderable::String(value.to_owned().into()))
+  }
+
+  fn visit_string<E>(self, value: String) -> Result<Renderable<'a>, E> {
+    Ok(Renderable::String(value.into()))
+  }
+
+  fn visit_seq<A>(self, mut seq: A) -> Result<Renderable<'a>, A::Error>
+  where
+    A: SeqAccess<'de>,
+  {
+    let mut children = Vec::new();
+    while let Some(child) = seq.next_element()? {
+      children.push(child);
+    }
+    Ok(Renderable::Fragment(children))
+  }
+
+  fn visit_map<A>(self, mut map: A) -> Result<Renderable<'a>, A::Error>
+  where
+    A: MapAccess<'de>,
+  {
+    let mut tag = false;
+    let mut name = None;
+    let mut attributes = None;
+    let mut children: Option<Vec<Renderable<'a>>> = None;
+
+    while let Some(key) = map.next_key::<Value<'a>>()? {
+      match key {
+        Value::String(key) if key.as_ref() == "$$mdtype" => match map.next_value()? {
+          Value::String(kind) if kind.as_ref() == "Tag" => tag = true,
+          _ => {
+            return Err(de::Error::custom(
+              "only Markdoc Tag objects can be rendered",
+            ))
+          }
+        },
+        Value::String(key) if key.as_ref() == "name" => match map.next_value()? {
+          Value::String(value) => name = Some(value),
+          _ => return Err(de::Error::custom("tag name must be a string")),
+        },
+        Value::String(key) if key.as_ref() == "attributes" => match map.next_value()? {
+          Value::Hash(attrs) => attributes = Some(attrs).filter(|attrs| !attrs.is_empty()),
+          Value::Null => attributes = None,
+          _ => return Err(de::Error::custom("tag attributes must be an object")),
+        },
+        Value::String(key) if key.as_ref() == "children" => {
+          children = map
+            .next_value::<Option<Vec<Renderable<'a>>>>()?
+            .filter(|children| !children.is_empty());
+        }
+        _ => {
+          map.next_value::<IgnoredAny>()?;
+        }
+      }
+    }
+
+    match (tag, name) {
+      (true, Some(name)) => Ok(Renderable::Tag {
+        name,
+        attributes,
+        children,
+      }),
+      (false, _) => Err(de::Error::missing_field("$$mdtype")),
+      (true, None) => Err(de::Error::missing_field("name")),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::model::schema::{Attribute, Config, Schema, Variables};
+  use crate::parse::parse;
+  use crate::render::html::render;
+  use crate::resolve::resolve_node;
+  use crate::schema::default_nodes;
+  use crate::transform::transform_node;
+  use indexmap::IndexMap;
+
+  fn html(node: &Renderable) -> String {
+    let mut output = Vec::new();
+    render(node, &mut output).expect("completes");
+    String::from_utf8(output).unwrap()
+  }
+
+  #[test]
+  fn serialize_tag_json() {
+    let config = Config {
+      nodes: default_nodes(),
+      tags: hash!(
+        "section" => Schema {
+          render: Some("section"),
+          attributes: Some(IndexMap::from([("id", Attribute::default())])),
+          ..Default::default()
+        }
+      ),
+      variables: Variables::Values(mdattrs!(id = "intro")).into(),
+      ..Default::default()
+    };
+
+    let doc = parse("{% section id=$id %}\nSay \"hi\"\n{% /section %}\n");
+    resolve_node(&doc.borrow(), &config).expect("resolves");
+    let content = transform_node(&doc.borrow(), &config);
+
+    assert_eq!(
+      serde_json::to_string(&content).unwrap(),
+      r#"[{"$$mdtype":"Tag","name":"section","attributes":{"id":"intro"},"children":[{"$$mdtype":"Tag","name":"p","attributes":{},"children":[["Say \"hi\""]]}]}]"#
+    );
+  }
+
+  #[test]
+  fn deserialize_tag_json() {
+    let json = r#"[{"$$mdtype":"Tag","name":"p","attributes":{"class":"lead"},"children":["One & ",{"$$mdtype":"Tag","name":"em","attributes":{},"children":["two"]},null,3]}]"#;
+    let content: Renderable = serde_json::from_str(json).unwrap();
+
+    assert_eq!(
+      html(&content),
+      r#"<p class="lead">One &amp; <em>two</em>3</p>"#
+    );
+    assert_eq!(
+      serde_json::to_string(&content).unwrap(),
+      json.replace("null,3", "null,\"3\"")
+    );
+  }
+
+  #[test]
+  fn deserialize_requires_tags() {
+    let untyped = serde_json::from_str::<Renderable>(r#"{"name":"p","children":[]}"#);
+    assert!(untyped.is_err());
+
+    let node = serde_json::from_str::<Renderable>(
+      r#"{"$$mdtype":"Node","type":"paragraph","name":"p","children":[]}"#,
+    );
+    assert!(node.is_err());
+
+    let nested = serde_json::from_str::<Renderable>(r#"["text",{"name":"script"}]"#);
+    assert!(nested.is_err());
+  }
+}
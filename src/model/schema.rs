@@ -1,8 +1,10 @@
 use super::render::Renderable;
 use super::Attributes;
 use super::{node::*, value::Value};
-use serde::{Deserialize, Serialize};
+use indexmap::IndexMap;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum SchemaType {
@@ -13,6 +15,8 @@ pub enum SchemaType {
   Array,
 }
 
+pub static GLOBAL_ATTRIBUTES: &[&str] = &["class", "id"];
+
 #[derive(Serialize)]
 pub enum AttributeRender<'a> {
   Name(&'a str),
@@ -36,41 +40,107 @@ pub struct Attribute<'a> {
   pub required: bool,
 }
 
-pub type TransformFn<'a> = fn(&Node<'a>, &'a Config<'a>) -> Renderable<'a>;
+// Hooks are shared closures rather than `fn` pointers so they can capture
+// runtime state. They're generic over the document lifetime, which keeps them
+// `'static` and lets a `Config` still be deserialized and borrowed as usual.
+pub type TransformFn =
+  Arc<dyn for<'a> Fn(&Node<'a>, &'a Config<'a>) -> Renderable<'a> + Send + Sync>;
+
+pub fn transform_fn<F>(f: F) -> TransformFn
+where
+  F: for<'a> Fn(&Node<'a>, &'a Config<'a>) -> Renderable<'a> + Send + Sync + 'static,
+{
+  Arc::new(f)
+}
 
 #[derive(Default, Serialize, Deserialize)]
 pub struct Schema<'a> {
   #[serde(default)]
   pub render: Option<&'a str>,
   #[serde(default)]
-  pub attributes: Option<HashMap<&'a str, Attribute<'a>>>,
+  pub attributes: Option<IndexMap<&'a str, Attribute<'a>>>,
   #[serde(default)]
   pub self_closing: bool,
   #[serde(skip)]
-  pub transform: Option<TransformFn<'a>>,
+  pub transform: Option<TransformFn>,
 }
 
-pub type EvaluateFn<'a> = fn(&Attributes<'a>, &'a Config<'a>) -> Value<'a>;
+pub type EvaluateFn =
+  Arc<dyn for<'a> Fn(&Attributes<'a>, &'a Config<'a>) -> Value<'a> + Send + Sync>;
+
+pub fn evaluate_fn<F>(f: F) -> EvaluateFn
+where
+  F: for<'a> Fn(&Attributes<'a>, &'a Config<'a>) -> Value<'a> + Send + Sync + 'static,
+{
+  Arc::new(f)
+}
 
 pub struct FunctionSchema<'a> {
-  pub attributes: Option<HashMap<&'a str, Attribute<'a>>>,
-  pub evaluate: EvaluateFn<'a>,
+  pub attributes: Option<IndexMap<&'a str, Attribute<'a>>>,
+  pub evaluate: EvaluateFn,
+}
+
+pub type VariableFn = Arc<dyn for<'a> Fn(&[Value<'a>]) -> Value<'a> + Send + Sync>;
+
+pub fn variable_fn<F>(f: F) -> VariableFn
+where
+  F: for<'a> Fn(&[Value<'a>]) -> Value<'a> + Send + Sync + 'static,
+{
+  Arc::new(f)
 }
 
-pub type VariableFn<'a> = fn(&[Value<'a>]) -> Value<'a>;
 pub enum Variables<'a> {
-  Resolver(VariableFn<'a>),
+  Resolver(VariableFn),
   Values(Attributes<'a>),
 }
 
-#[derive(Serialize, Deserialize)]
+// Supplies the source of included files by name, see `crate::partial` for the
+// map and directory implementations
+pub trait PartialProvider {
+  fn get(&self, file: &str) -> Option<&str>;
+}
+
+fn values<'de: 'a, 'a, D>(deserializer: D) -> Result<Option<Variables<'a>>, D::Error>
+where
+  D: Deserializer<'de>,
+{
+  match Value::deserialize(deserializer)? {
+    Value::Hash(values) => Ok(Some(Variables::Values(values))),
+    Value::Null => Ok(None),
+    _ => Err(serde::de::Error::custom("variables must be an object")),
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnknownTagPolicy {
+  #[default]
+  Drop,
+  Children,
+  Placeholder,
+}
+
+#[derive(Default, Serialize, Deserialize)]
 pub struct Config<'a> {
-  #[serde(borrow)]
+  #[serde(default, borrow)]
   pub nodes: HashMap<Type, Schema<'a>>,
-  #[serde(borrow)]
+  #[serde(default, borrow)]
   pub tags: HashMap<&'a str, Schema<'a>>,
-  #[serde(skip)]
+  // Document variables, referenced as `$name`. A JSON config can only give
+  // them as an object of values.
+  #[serde(default, skip_serializing, borrow, deserialize_with = "values")]
   pub variables: Option<Variables<'a>>,
+  // Special variables, referenced as `@name`, for values supplied by the
+  // rendering environment rather than the author, like `@page` or `@index`.
+  // The engine adds `@file` inside partials.
+  #[serde(skip)]
+  pub special_variables: Option<Variables<'a>>,
   #[serde(skip)]
   pub functions: Option<HashMap<&'a str, FunctionSchema<'a>>>,
+  #[serde(default)]
+  pub unknown_tags: UnknownTagPolicy,
+  #[serde(skip)]
+  pub partials: Option<Box<dyn PartialProvider + Send + Sync>>,
+  #[serde(default)]
+  pub strict: bool,
 }
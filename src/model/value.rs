@@ -1,9 +1,12 @@
+use super::location::Location;
 use super::Attributes;
 use pulldown_cmark::CowStr;
+use serde::de::{MapAccess, SeqAccess, Visitor};
 use serde::ser::SerializeMap;
-use serde::{Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::cell::RefCell;
 use std::fmt;
+use std::marker::PhantomData;
 use std::rc::Rc;
 
 pub type ValueRef<'a> = Rc<RefCell<Value<'a>>>;
@@ -15,7 +18,9 @@ pub enum Value<'a> {
   String(CowStr<'a>),
   Number(f64),
   Boolean(bool),
+  #[serde(serialize_with = "serialize_expression")]
   Expression(ValueRef<'a>, Expression<'a>),
+  #[serde(serialize_with = "serialize_undefined")]
   Undefined,
   Null,
 }
@@ -28,7 +33,17 @@ impl<'a> Value<'a> {
     }
   }
 
-  pub fn deep_get(&'a self, path: &[Value<'a>]) -> Option<&'a Value<'a>> {
+  // Markdoc only treats false, null, and undefined as falsy, so zero and
+  // empty strings count as true in conditions and class lists alike
+  pub fn truthy(&self) -> bool {
+    match self {
+      Value::Expression(value, _) => value.borrow().truthy(),
+      Value::Boolean(false) | Value::Undefined | Value::Null => false,
+      _ => true,
+    }
+  }
+
+  pub fn deep_get<'b>(&'b self, path: &[Value<'a>]) -> Option<&'b Value<'a>> {
     path
       .into_iter()
       .fold(Some(self), |cur, iter| match (cur, iter) {
@@ -99,26 +114,188 @@ impl<'a> fmt::Display for Value<'a> {
   }
 }
 
-#[derive(PartialEq, Clone, Debug, Serialize)]
+// The resolved value is runtime state, so only the expression is written out
+fn serialize_expression<S>(_: &ValueRef, expression: &Expression, s: S) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+{
+  expression.serialize(s)
+}
+
+// JSON has no undefined, so it's written as a marker that reads back as one
+// rather than as a null
+fn serialize_undefined<S>(s: S) -> Result<S::Ok, S::Error>
+where
+  S: Serializer,
+{
+  let mut map = s.serialize_map(Some(1))?;
+  map.serialize_entry("$$mdtype", "Undefined")?;
+  map.end()
+}
+
+impl<'de: 'a, 'a> Deserialize<'de> for Value<'a> {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    deserializer.deserialize_any(ValueVisitor(PhantomData))
+  }
+}
+
+struct ValueVisitor<'a>(PhantomData<Value<'a>>);
+
+impl<'de: 'a, 'a> Visitor<'de> for ValueVisitor<'a> {
+  type Value = Value<'a>;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("a Markdoc value")
+  }
+
+  fn visit_unit<E>(self) -> Result<Value<'a>, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_none<E>(self) -> Result<Value<'a>, E> {
+    Ok(Value::Null)
+  }
+
+  fn visit_bool<E>(self, value: bool) -> Result<Value<'a>, E> {
+    Ok(Value::Boolean(value))
+  }
+
+  fn visit_i64<E>(self, value: i64) -> Result<Value<'a>, E> {
+    Ok(Value::Number(value as f64))
+  }
+
+  fn visit_u64<E>(self, value: u64) -> Result<Value<'a>, E> {
+    Ok(Value::Number(value as f64))
+  }
+
+  fn visit_f64<E>(self, value: f64) -> Result<Value<'a>, E> {
+    Ok(Value::Number(value))
+  }
+
+  fn visit_borrowed_str<E>(self, value: &'de str) -> Result<Value<'a>, E> {
+    Ok(Value::String(CowStr::Borrowed(value)))
+  }
+
+  fn visit_str<E>(self, value: &str) -> Result<Value<'a>, E> {
+    Ok(Value::from(value.to_owned()))
+  }
+
+  fn visit_string<E>(self, value: String) -> Result<Value<'a>, E> {
+    Ok(Value::from(value))
+  }
+
+  fn visit_seq<A>(self, mut seq: A) -> Result<Value<'a>, A::Error>
+  where
+    A: SeqAccess<'de>,
+  {
+    let mut items = Vec::new();
+    while let Some(item) = seq.next_element()? {
+      items.push(item);
+    }
+    Ok(Value::Array(items))
+  }
+
+  fn visit_map<A>(self, mut map: A) -> Result<Value<'a>, A::Error>
+  where
+    A: MapAccess<'de>,
+  {
+    let mut attrs = Attributes::new();
+    while let Some((key, value)) = map.next_entry::<Value<'a>, Value<'a>>()? {
+      match key {
+        Value::String(key) => attrs.insert(key, value),
+        _ => return Err(serde::de::Error::custom("keys must be strings")),
+      };
+    }
+
+    Ok(expression(&attrs).unwrap_or(Value::Hash(attrs)))
+  }
+}
+
+// Only maps shaped exactly like the ones written out below are read back as
+// expressions, so a hash that happens to carry `$$mdtype` stays a hash
+fn expression<'a>(attrs: &Attributes<'a>) -> Option<Value<'a>> {
+  let keys = |allowed: &[&str]| attrs.keys().all(|key| allowed.contains(&key.as_ref()));
+
+  match attrs.get("$$mdtype") {
+    Some(Value::String(kind)) => match kind.as_ref() {
+      "Variable" if keys(&["$$mdtype", "sigil", "path"]) => variable(attrs),
+      "Function" if keys(&["$$mdtype", "name", "parameters"]) => function(attrs),
+      "Undefined" if attrs.len() == 1 => Some(Value::Undefined),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+fn variable<'a>(attrs: &Attributes<'a>) -> Option<Value<'a>> {
+  let sigil = match attrs.get("sigil") {
+    Some(Value::String(sigil)) if sigil.as_ref() == "@" => '@',
+    Some(Value::String(sigil)) if sigil.as_ref() == "$" => '$',
+    None => '$',
+    _ => return None,
+  };
+
+  match attrs.get("path") {
+    Some(Value::Array(path)) => Some(Expression::Variable(sigil, path.clone()).into()),
+    _ => None,
+  }
+}
+
+fn function<'a>(attrs: &Attributes<'a>) -> Option<Value<'a>> {
+  let name = match attrs.get("name") {
+    Some(Value::String(name)) => name.clone(),
+    _ => return None,
+  };
+
+  match attrs.get("parameters") {
+    Some(Value::Hash(parameters)) => Some(parameters.clone()),
+    None => Some(Attributes::new()),
+    _ => None,
+  }
+  .map(|parameters| Expression::Function(name, parameters, None).into())
+}
+
+#[derive(Clone, Debug, Serialize)]
 #[serde(untagged)]
 pub enum Expression<'a> {
   #[serde(serialize_with = "serialize_variable")]
   Variable(char, Vec<Value<'a>>),
+  // Calls parsed from a document record where they are, for diagnostics.
+  // The location is left out of the JSON AST and of comparisons.
   #[serde(serialize_with = "serialize_function")]
-  Function(CowStr<'a>, Attributes<'a>),
+  Function(CowStr<'a>, Attributes<'a>, Option<Location>),
+}
+
+impl<'a> PartialEq for Expression<'a> {
+  fn eq(&self, other: &Self) -> bool {
+    match (self, other) {
+      (Expression::Variable(a, b), Expression::Variable(c, d)) => a == c && b == d,
+      (Expression::Function(a, b, _), Expression::Function(c, d, _)) => a == c && b == d,
+      _ => false,
+    }
+  }
 }
 
-fn serialize_variable<S>(_ch: &char, path: &[Value], s: S) -> Result<S::Ok, S::Error>
+fn serialize_variable<S>(sigil: &char, path: &[Value], s: S) -> Result<S::Ok, S::Error>
 where
   S: Serializer,
 {
-  let mut map = s.serialize_map(Some(3))?;
+  let mut map = s.serialize_map(Some(4))?;
   map.serialize_entry("$$mdtype", "Variable")?;
+  map.serialize_entry("sigil", sigil)?;
   map.serialize_entry("path", &path)?;
   map.end()
 }
 
-fn serialize_function<S>(name: &str, attrs: &Attributes, s: S) -> Result<S::Ok, S::Error>
+fn serialize_function<S>(
+  name: &str,
+  attrs: &Attributes,
+  _: &Option<Location>,
+  s: S,
+) -> Result<S::Ok, S::Error>
 where
   S: Serializer,
 {
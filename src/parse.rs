@@ -1,42 +1,240 @@
+use crate::frontmatter;
+use crate::model::location::LineIndex;
 use crate::model::node::*;
+use crate::model::schema::{Config, PartialProvider};
 use crate::model::value::*;
 use crate::model::*;
+use crate::schema::default_tags;
 use crate::tag;
 use crate::tokenize::{tokenize, Events};
-use pulldown_cmark::{scan_markdoc_tag_end, CodeBlockKind, Event, Tag as EventTag};
-use std::ops::Deref;
+use pulldown_cmark::{scan_markdoc_tag_end, CodeBlockKind, CowStr, Event, Tag as EventTag};
+use std::ops::Range;
 use tag::Tag;
 
-pub fn extract_frontmatter(input: &str) -> Option<(&str, &str)> {
-  input
-    .strip_prefix("---")
-    .and_then(|left| left.split_once("---"))
+pub fn extract_frontmatter(input: &str) -> Option<(FrontmatterKind, Range<usize>, &str)> {
+  let mut lines = input.split_inclusive('\n');
+  let first = lines.next()?;
+  let fence = first.trim_end();
+
+  let kind = match fence {
+    "---" => FrontmatterKind::Yaml,
+    "+++" => FrontmatterKind::Toml,
+    ";;;" => FrontmatterKind::Json,
+    _ => return None,
+  };
+
+  let start = first.len();
+  let mut end = start;
+
+  for line in lines {
+    if line.trim_end() == fence {
+      return Some((kind, start..end, &input[end + line.len()..]));
+    }
+
+    end += line.len();
+  }
+
+  None
+}
+
+#[derive(Default)]
+pub struct ParseOptions<'c> {
+  pub config: Option<&'c Config<'c>>,
+  pub frontmatter: bool,
 }
 
+impl<'c> ParseOptions<'c> {
+  // Tags the config doesn't define fall back to the built-in schemas, so
+  // built-in tags like `else` never take children, even without a config
+  fn self_closing(&self, tag: &str) -> bool {
+    match self.config.and_then(|config| config.tags.get(tag)) {
+      Some(schema) => schema.self_closing,
+      None => default_tags()
+        .get(tag)
+        .map(|schema| schema.self_closing)
+        .unwrap_or(false),
+    }
+  }
+}
+
+// Parses without a config, so only built-in tags like `else` are known to be
+// self-closing. A tag with `Schema.self_closing` written without `/%}` takes
+// the content after it as children; use `parse_with_options` with the
+// config to avoid that, or `validate` reports it as `self-closing-children`.
 pub fn parse(input: &str) -> NodeRef {
+  parse_with_options(input, &ParseOptions::default())
+}
+
+pub fn parse_with_options<'a>(input: &'a str, options: &ParseOptions<'a>) -> NodeRef<'a> {
+  let root = parse_document(input, options);
+
+  if let Some(partials) = options.config.and_then(|config| config.partials.as_deref()) {
+    expand_partials(&root, partials, options, &mut Vec::new());
+  }
+
+  root
+}
+
+fn parse_document<'a>(input: &'a str, options: &ParseOptions) -> NodeRef<'a> {
   let mut attributes = None;
+  let mut errors = None;
   let mut offset = 0;
   let mut source = input;
+  let mut frontmatter_source = None;
+  let index = LineIndex::new(input);
+
+  if let Some((kind, location, text)) = extract_frontmatter(input) {
+    let frontmatter = &input[location.clone()];
+
+    attributes = Some(if options.frontmatter {
+      match frontmatter::parse(kind, frontmatter, location.clone(), &index) {
+        Ok(values) => mdattrs!(frontmatter = values),
+        Err(error) => {
+          errors = Some(vec![error]);
+          mdattrs!(frontmatter = frontmatter.trim())
+        }
+      }
+    } else {
+      mdattrs!(frontmatter = frontmatter.trim())
+    });
 
-  if let Some((frontmatter, text)) = extract_frontmatter(input) {
-    attributes = Some(mdattrs!(frontmatter = frontmatter.trim()));
     offset = input.len() - text.len();
     source = text;
+    frontmatter_source = Some(Frontmatter {
+      kind,
+      location: index.location(location),
+    });
   }
 
   let root = NodeRef::from(Node {
     kind: Type::Document,
-    location: Some(0..input.len()),
+    location: Some(index.location(0..input.len())),
     attributes,
+    errors,
+    frontmatter_source,
     ..Node::default()
   });
 
   let events = tokenize(source);
   let mut nodes = vec![root.clone()];
-  convert_events(source, &mut nodes, events.collect(), offset);
+  convert_events(
+    source,
+    &mut nodes,
+    events.collect(),
+    offset,
+    &index,
+    options,
+  );
   root
 }
 
+fn expand_partials<'a>(
+  node: &NodeRef<'a>,
+  partials: &'a dyn PartialProvider,
+  options: &ParseOptions<'a>,
+  stack: &mut Vec<String>,
+) {
+  let children = node.borrow().children.clone().unwrap_or_default();
+
+  for child in children {
+    let file = match &*child.borrow() {
+      Node {
+        kind: Type::Tag(..),
+        tag: Some(tag),
+        ..
+      } if tag.as_ref() == "partial" => match child.borrow().attribute("file") {
+        Some(Value::String(file)) => Some(Ok(file.to_string())),
+        _ => Some(Err(())),
+      },
+      _ => None,
+    };
+
+    match file {
+      Some(Ok(file)) => graft_partial(&child, file, partials, options, stack),
+      // Partials are grafted while parsing, before any variables are known
+      Some(Err(())) => {
+        let mut node = child.borrow_mut();
+        let error = Error {
+          id: "partial-file-invalid",
+          level: ErrorLevel::Critical,
+          message: "Partial file must be a string literal".into(),
+          location: node.location.clone(),
+        };
+
+        node.push_error(error);
+      }
+      None => expand_partials(&child, partials, options, stack),
+    }
+  }
+}
+
+fn graft_partial<'a>(
+  node: &NodeRef<'a>,
+  file: String,
+  partials: &'a dyn PartialProvider,
+  options: &ParseOptions<'a>,
+  stack: &mut Vec<String>,
+) {
+  let mut node = node.borrow_mut();
+
+  if stack.contains(&file) {
+    let error = Error {
+      id: "partial-cycle",
+      level: ErrorLevel::Critical,
+      message: format!("Partial '{}' includes itself", file),
+      location: node.location.clone(),
+    };
+
+    return node.push_error(error);
+  }
+
+  let source = match partials.get(&file) {
+    Some(source) => source,
+    None => {
+      let error = Error {
+        id: "partial-not-found",
+        level: ErrorLevel::Critical,
+        message: format!("Partial '{}' not found", file),
+        location: node.location.clone(),
+      };
+
+      return node.push_error(error);
+    }
+  };
+
+  let document = parse_document(source, options);
+  set_file(&mut document.borrow_mut(), &file);
+
+  stack.push(file);
+  expand_partials(&document, partials, options, stack);
+  stack.pop();
+
+  let mut document = document.borrow_mut();
+  for error in document.errors.take().into_iter().flatten() {
+    node.push_error(error);
+  }
+
+  node.children = document.children.take();
+}
+
+fn set_file(node: &mut Node, file: &str) {
+  let locations = node.location.iter_mut().chain(
+    node
+      .errors
+      .iter_mut()
+      .flatten()
+      .filter_map(|error| error.location.as_mut()),
+  );
+
+  for location in locations {
+    location.file = Some(file.to_owned());
+  }
+
+  for child in node.children.iter().flatten() {
+    set_file(&mut child.borrow_mut(), file);
+  }
+}
+
 fn add_child<'a>(nodes: &mut Vec<NodeRef<'a>>, child: NodeRef<'a>, push: bool) {
   if let Some(last) = nodes.last() {
     last.borrow_mut().push(child.clone());
@@ -54,6 +252,31 @@ fn parent_kind(nodes: &[NodeRef], kind: Type) -> bool {
     .unwrap_or(false)
 }
 
+fn parent_tag(nodes: &[NodeRef]) -> bool {
+  nodes.len() > 1
+    && nodes
+      .last()
+      .map(|x| matches!(x.borrow().kind, Type::Tag(..)))
+      .unwrap_or(false)
+}
+
+fn pop_unclosed(nodes: &mut Vec<NodeRef>) {
+  if let Some(node) = nodes.pop() {
+    let mut node = node.borrow_mut();
+    let error = Error {
+      id: "missing-closing",
+      level: ErrorLevel::Critical,
+      message: format!(
+        "Tag '{}' is missing closing",
+        node.tag.as_deref().unwrap_or_default()
+      ),
+      location: node.location.clone(),
+    };
+
+    node.push_error(error);
+  }
+}
+
 fn event_type(event: &Event) -> Type {
   match event {
     Event::End(tag) | Event::Start(tag) => match tag {
@@ -89,16 +312,43 @@ pub fn convert_events<'a>(
   nodes: &mut Vec<NodeRef<'a>>,
   events: Events<'a>,
   offset: usize,
+  index: &LineIndex,
+  options: &ParseOptions,
 ) {
   let mut last_inline: Option<NodeRef> = None;
   let mut inside_thead = false;
+  let mut inside_fence = false;
+  let mut fence_text: Option<(Range<usize>, String)> = None;
 
   for (event, range) in events {
+    // Raw HTML is disabled like in JS Markdoc, so it's kept as text
+    let event = match event {
+      Event::Html(html) => Event::Text(html),
+      event => event,
+    };
+
     let kind = event_type(&event);
-    let offset_range = range.start + offset..range.end + offset;
+    let location = index.location(range.start + offset..range.end + offset);
+
+    if inside_fence {
+      if let Event::Text(text) = &event {
+        let (fence_range, content) =
+          fence_text.get_or_insert_with(|| (range.start..range.start, String::new()));
+        fence_range.end = range.end;
+        content.push_str(text);
+        continue;
+      }
+    }
+
+    // Tags opened inside a block can't outlive it
+    if let Event::End(_) = event {
+      while parent_tag(nodes) {
+        pop_unclosed(nodes);
+      }
+    }
 
     if last_inline.is_none() && kind.is_inline() {
-      let inline_node = mdnode!(Type::Inline, None);
+      let inline_node = mdnode!(Type::Inline);
       if let Some(parent) = nodes.last_mut() {
         last_inline = Some(parent.clone());
       }
@@ -113,30 +363,37 @@ pub fn convert_events<'a>(
 
     match event {
       Event::Text(text) | Event::Code(text) => {
-        add_child(nodes, mdnode!(kind, offset_range, content = text), false);
+        add_child(nodes, mdnode!(kind, location, content = text), false);
       }
 
       Event::SoftBreak | Event::HardBreak => {
-        add_child(nodes, mdnode!(kind, offset_range), false);
+        add_child(nodes, mdnode!(kind, location), false);
       }
 
       Event::MarkdocTag(_, inline) => {
-        let tag = tag::parse(&input[range.clone()]);
+        let tag = tag::parse_at(&input[range.clone()], Some((index, range.start + offset)));
         let push = matches!(&tag, Tag::Open(..));
 
         match tag {
           Tag::Open(name, attributes) | Tag::Standalone(name, attributes) => {
-            let node = mdnode!(Type::Tag(inline), offset_range, attributes);
+            let node = mdnode!(Type::Tag(inline), location, attributes);
             node.borrow_mut().tag = Some(name.into());
-            add_child(nodes, node, push);
+            add_child(nodes, node, push && !options.self_closing(name));
           }
 
           Tag::Close(name) => {
-            if nodes
-              .last()
-              .and_then(|x| x.borrow().tag.as_ref().map(|x| x.deref() == name))
-              .unwrap_or(false)
-            {
+            let depth = nodes
+              .iter()
+              .skip(1)
+              .rev()
+              .take_while(|x| matches!(x.borrow().kind, Type::Tag(..)))
+              .position(|x| x.borrow().tag.as_deref() == Some(name));
+
+            if let Some(depth) = depth {
+              for _ in 0..depth {
+                pop_unclosed(nodes);
+              }
+
               nodes.pop();
               continue;
             }
@@ -145,13 +402,13 @@ pub fn convert_events<'a>(
               id: "missing-opening",
               level: ErrorLevel::Critical,
               message: format!("Tag '{}' is missing opening", name),
-              location: Some(offset_range.clone()),
+              location: Some(location.clone()),
             };
 
             let node = Node {
               kind: Type::Tag(inline),
               tag: Some(name.into()),
-              location: Some(offset_range),
+              location: Some(location),
               errors: vec![error].into(),
               ..Node::default()
             };
@@ -166,7 +423,7 @@ pub fn convert_events<'a>(
           }
 
           Tag::Value(variable) => {
-            let node = mdnode!(Type::Text, offset_range, content = variable);
+            let node = mdnode!(Type::Text, location, content = variable);
             add_child(nodes, node, false);
           }
 
@@ -175,12 +432,12 @@ pub fn convert_events<'a>(
               id: "syntax-error",
               level: ErrorLevel::Critical,
               message: format!("{}", error),
-              location: Some(offset_range.clone()),
+              location: Some(location.clone()),
             };
 
             let node = Node {
               kind: Type::Error,
-              location: Some(offset_range),
+              location: Some(location),
               errors: vec![err].into(),
               ..Node::default()
             };
@@ -192,7 +449,7 @@ pub fn convert_events<'a>(
 
       Event::Start(tag) => match tag {
         EventTag::Heading(level, ..) => {
-          let node = mdnode!(kind, offset_range, level = level as i32);
+          let node = mdnode!(kind, location, level = level as i32);
           add_child(nodes, node, true);
         }
 
@@ -202,7 +459,7 @@ pub fn convert_events<'a>(
             None => mdattrs!(ordered = false),
           };
 
-          add_child(nodes, mdnode!(kind, offset_range, attrs), true);
+          add_child(nodes, mdnode!(kind, location, attrs), true);
         }
 
         EventTag::Link(_, link, title) => {
@@ -212,7 +469,7 @@ pub fn convert_events<'a>(
             mdattrs!(href = link, title = title)
           };
 
-          add_child(nodes, mdnode!(kind, offset_range, attrs), true);
+          add_child(nodes, mdnode!(kind, location, attrs), true);
         }
 
         EventTag::Image(_, link, title) => {
@@ -222,14 +479,16 @@ pub fn convert_events<'a>(
             mdattrs!(src = link, title = title)
           };
 
-          add_child(nodes, mdnode!(kind, offset_range, attrs), true);
+          add_child(nodes, mdnode!(kind, location, attrs), true);
         }
 
-        EventTag::CodeBlock(CodeBlockKind::Fenced(info)) => {
+        // Indented blocks are disabled in the tokenizer, but if one shows up
+        // it collects its text like a fence without an info string
+        EventTag::CodeBlock(code) => {
           let content = &input[range.clone()];
           let mut attributes = Attributes::new();
 
-          if let (Some(start), Some(_end)) = (content.find('\n'), content.rfind('\n')) {
+          if let (CodeBlockKind::Fenced(info), Some(start)) = (code, content.find('\n')) {
             if let Some(lang) = info.split_ascii_whitespace().next() {
               if !lang.trim().starts_with("{%") {
                 attributes.insert("language".into(), Value::from(lang.to_owned()));
@@ -243,15 +502,16 @@ pub fn convert_events<'a>(
                 }
               }
             }
-
-            add_child(nodes, mdnode!(kind, offset_range, attributes), true);
           };
+
+          add_child(nodes, mdnode!(kind, location, attributes), true);
+          inside_fence = true;
         }
 
         EventTag::TableHead => {
           inside_thead = true;
-          add_child(nodes, mdnode!(Type::TableHead, offset_range.clone()), true);
-          add_child(nodes, mdnode!(Type::TableRow, offset_range), true);
+          add_child(nodes, mdnode!(Type::TableHead, location.clone()), true);
+          add_child(nodes, mdnode!(Type::TableRow, location), true);
         }
 
         EventTag::TableCell => {
@@ -261,11 +521,11 @@ pub fn convert_events<'a>(
             Type::TableCell
           };
 
-          add_child(nodes, mdnode!(kind, offset_range), true);
+          add_child(nodes, mdnode!(kind, location), true);
         }
 
         _ => {
-          add_child(nodes, mdnode!(kind, offset_range), true);
+          add_child(nodes, mdnode!(kind, location), true);
         }
       },
 
@@ -274,10 +534,19 @@ pub fn convert_events<'a>(
         nodes.pop();
 
         inside_thead = false;
-        let node = mdnode!(Type::TableBody, None);
+        let node = mdnode!(Type::TableBody);
         add_child(nodes, node.clone(), true);
       }
 
+      Event::End(EventTag::CodeBlock(..)) => {
+        inside_fence = false;
+
+        if let Some(fence) = nodes.pop() {
+          let (range, text) = fence_text.take().unwrap_or_default();
+          convert_fence(input, &fence, range, text, offset, index, options);
+        }
+      }
+
       Event::End(tag) => {
         if matches!(tag, EventTag::Table(..)) {
           nodes.pop();
@@ -286,9 +555,482 @@ pub fn convert_events<'a>(
         nodes.pop();
       }
 
-      Event::Rule => add_child(nodes, mdnode!(Type::Rule, range), false),
+      Event::Rule => add_child(nodes, mdnode!(Type::Rule, location), false),
 
       _ => (),
     }
   }
+
+  while parent_tag(nodes) {
+    pop_unclosed(nodes);
+  }
+}
+
+fn convert_fence<'a>(
+  input: &'a str,
+  fence: &NodeRef<'a>,
+  range: Range<usize>,
+  text: String,
+  offset: usize,
+  index: &LineIndex,
+  options: &ParseOptions,
+) {
+  let location = index.location(range.start + offset..range.end + offset);
+  let source = &input[range.clone()];
+
+  // Fences inside containers like blockquotes have line prefixes stripped, so
+  // their content isn't a slice of the input and is always treated as literal
+  let content: CowStr<'a> = if source == text {
+    source.into()
+  } else {
+    text.into()
+  };
+
+  let process = !matches!(
+    fence.borrow().attribute("process"),
+    Some(Value::Boolean(false))
+  );
+
+  fence
+    .borrow_mut()
+    .set_attribute("content".into(), content.clone().into());
+
+  // Literal content is wrapped the same way processed content is
+  if !process || source != content.as_ref() {
+    let inline = mdnode!(Type::Inline);
+    inline
+      .borrow_mut()
+      .push(mdnode!(Type::Text, location, content = content));
+    fence.borrow_mut().push(inline);
+    return;
+  }
+
+  let mut events = Events::new();
+  let mut pos = 0;
+
+  while let Some(start) = source[pos..].find("{%").map(|start| pos + start) {
+    let end = match scan_markdoc_tag_end(&source.as_bytes()[start..]) {
+      Some(end) => start + end,
+      None => break,
+    };
+
+    if start > pos {
+      let text = Event::Text(source[pos..start].into());
+      events.push((text, range.start + pos..range.start + start));
+    }
+
+    let tag = Event::MarkdocTag(source[start..end].into(), true);
+    events.push((tag, range.start + start..range.start + end));
+    pos = end;
+  }
+
+  if pos < source.len() {
+    let text = Event::Text(source[pos..].into());
+    events.push((text, range.start + pos..range.end));
+  }
+
+  convert_events(
+    input,
+    &mut vec![fence.clone()],
+    events,
+    offset,
+    index,
+    options,
+  );
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::model::schema::Schema;
+
+  fn child<'a>(node: &NodeRef<'a>, index: usize) -> NodeRef<'a> {
+    node.borrow().children.as_ref().unwrap()[index].clone()
+  }
+
+  #[test]
+  fn unclosed_tag_at_end_of_document() {
+    let input = "{% foo %}\nThis is a test\n";
+    let doc = parse(input);
+    let foo = child(&doc, 0);
+    assert_eq!(
+      foo.borrow().errors,
+      Some(vec![Error {
+        id: "missing-closing",
+        level: ErrorLevel::Critical,
+        message: "Tag 'foo' is missing closing".into(),
+        location: Some(LineIndex::new(input).location(0..9)),
+      }])
+    );
+  }
+
+  #[test]
+  fn node_locations() {
+    let doc = parse("---\ntitle: Example\n---\n\n# Heading\n\nSome *text*\n");
+    let paragraph = child(&doc, 1);
+    let location = paragraph.borrow().location.clone().unwrap();
+    assert_eq!((location.start.line, location.start.character), (6, 0));
+    assert_eq!((location.end.line, location.end.character), (7, 0));
+
+    let emphasis = child(&child(&paragraph, 0), 1);
+    let location = emphasis.borrow().location.clone().unwrap();
+    assert_eq!((location.start.line, location.start.character), (6, 5));
+    assert_eq!(location.range(), 40..46);
+  }
+
+  #[test]
+  fn unclosed_tag_nested_in_closed_tag() {
+    let doc = parse("{% foo %}\n{% bar %}\nThis is a test\n{% /foo %}\n\nOutside\n");
+    let foo = child(&doc, 0);
+    let bar = child(&foo, 0);
+    assert_eq!(foo.borrow().errors, None);
+    assert_eq!(bar.borrow().tag.as_deref(), Some("bar"));
+    assert_eq!(
+      bar.borrow().errors.as_ref().map(|errors| errors[0].id),
+      Some("missing-closing")
+    );
+    assert_eq!(child(&doc, 1).borrow().kind, Type::Paragraph);
+  }
+
+  #[test]
+  fn mismatched_closing_tag() {
+    let doc = parse("{% foo %}\n{% bar %}\nThis is a test\n{% /baz %}\n{% /bar %}\n{% /foo %}\n");
+    let foo = child(&doc, 0);
+    let bar = child(&foo, 0);
+    let baz = child(&bar, 1);
+    assert_eq!(foo.borrow().errors, None);
+    assert_eq!(bar.borrow().errors, None);
+    assert_eq!(
+      baz.borrow().errors.as_ref().map(|errors| errors[0].id),
+      Some("missing-opening")
+    );
+  }
+
+  #[test]
+  fn raw_frontmatter() {
+    let doc = parse("---\ntitle: Example\n---\n\n# Heading\n");
+    assert_eq!(
+      doc.borrow().attribute("frontmatter"),
+      Some(&Value::from("title: Example"))
+    );
+    assert_eq!(doc.borrow().frontmatter(), None);
+  }
+
+  #[test]
+  fn frontmatter_requires_fence_lines() {
+    let doc = parse("---\n\nThis is a test\n");
+    assert_eq!(doc.borrow().frontmatter_source, None);
+    assert_eq!(child(&doc, 0).borrow().kind, Type::Rule);
+
+    let doc = parse("---\ntitle: a---b\n---\nThis is a test\n");
+    assert_eq!(
+      doc.borrow().attribute("frontmatter"),
+      Some(&Value::from("title: a---b"))
+    );
+    assert_eq!(
+      doc
+        .borrow()
+        .frontmatter_source
+        .as_ref()
+        .map(|x| (x.kind, x.location.range())),
+      Some((FrontmatterKind::Yaml, 4..17))
+    );
+  }
+
+  #[test]
+  fn toml_and_json_frontmatter() {
+    let options = ParseOptions {
+      frontmatter: true,
+      ..Default::default()
+    };
+
+    let doc = parse_with_options("+++\ntitle = \"Example\"\n+++\n", &options);
+    assert_eq!(
+      doc.borrow().frontmatter(),
+      Some(&mdattrs!(title = "Example"))
+    );
+    assert_eq!(
+      doc.borrow().frontmatter_source.as_ref().map(|x| x.kind),
+      Some(FrontmatterKind::Toml)
+    );
+
+    let doc = parse_with_options(";;;\n{\"title\": \"Example\"}\n;;;\n", &options);
+    assert_eq!(
+      doc.borrow().frontmatter(),
+      Some(&mdattrs!(title = "Example"))
+    );
+    assert_eq!(
+      doc
+        .borrow()
+        .frontmatter_source
+        .as_ref()
+        .map(|x| (x.kind, x.location.range())),
+      Some((FrontmatterKind::Json, 4..25))
+    );
+  }
+
+  #[test]
+  fn parsed_frontmatter() {
+    let options = ParseOptions {
+      frontmatter: true,
+      ..Default::default()
+    };
+
+    let doc = parse_with_options(
+      "---\ntitle: Example\ncount: 3\n---\n\n# Heading\n",
+      &options,
+    );
+    assert_eq!(
+      doc.borrow().frontmatter(),
+      Some(&mdattrs!(title = "Example", count = 3))
+    );
+  }
+
+  #[test]
+  fn malformed_frontmatter() {
+    let options = ParseOptions {
+      frontmatter: true,
+      ..Default::default()
+    };
+
+    let doc = parse_with_options("---\ntitle: [Example\n---\n\n# Heading\n", &options);
+    let doc = doc.borrow();
+    assert_eq!(doc.frontmatter(), None);
+    assert_eq!(
+      doc.errors.as_ref().map(|errors| errors[0].id),
+      Some("invalid-frontmatter")
+    );
+  }
+
+  #[test]
+  fn fence_content() {
+    let doc = parse("```js\nconst a = 1;\nconst b = {% $foo %};\n```\n");
+    let fence = child(&doc, 0);
+    assert_eq!(
+      fence.borrow().attribute("content"),
+      Some(&Value::from("const a = 1;\nconst b = {% $foo %};\n"))
+    );
+
+    let inline = child(&fence, 0);
+    assert_eq!(inline.borrow().kind, Type::Inline);
+    assert_eq!(
+      child(&inline, 1).borrow().attribute("content"),
+      Some(&Expression::Variable('$', vec!["foo".into()]).into())
+    );
+  }
+
+  #[test]
+  fn fence_without_processing() {
+    let doc = parse("```js {% process=false %}\nconst b = {% $foo %};\n```\n");
+    let fence = child(&doc, 0);
+    let inline = child(&fence, 0);
+    assert_eq!(fence.borrow().children.as_ref().map(|x| x.len()), Some(1));
+    assert_eq!(inline.borrow().kind, Type::Inline);
+    assert_eq!(
+      child(&inline, 0).borrow().attribute("content"),
+      Some(&Value::from("const b = {% $foo %};\n"))
+    );
+  }
+
+  #[test]
+  fn fence_in_container() {
+    let doc = parse("> ```js\n> const a = 1;\n> const b = {% $foo %};\n> ```\n");
+    let fence = child(&child(&doc, 0), 0);
+    assert_eq!(fence.borrow().kind, Type::Fence);
+
+    let inline = child(&fence, 0);
+    assert_eq!(inline.borrow().kind, Type::Inline);
+    assert_eq!(inline.borrow().children.as_ref().map(|x| x.len()), Some(1));
+    assert_eq!(
+      child(&inline, 0).borrow().attribute("content"),
+      Some(&Value::from("const a = 1;\nconst b = {% $foo %};\n"))
+    );
+  }
+
+  #[test]
+  fn indented_code() {
+    // Built from events since the tokenizer normally disables indented code
+    let input = "    let a = 1;\n";
+    let events = vec![
+      (
+        Event::Start(EventTag::CodeBlock(CodeBlockKind::Indented)),
+        0..15,
+      ),
+      (Event::Text("let a = 1;\n".into()), 4..15),
+      (
+        Event::End(EventTag::CodeBlock(CodeBlockKind::Indented)),
+        0..15,
+      ),
+    ];
+
+    let doc = mdnode!(Type::Document);
+    let index = LineIndex::new(input);
+    convert_events(
+      input,
+      &mut vec![doc.clone()],
+      events,
+      0,
+      &index,
+      &ParseOptions::default(),
+    );
+
+    let fence = child(&doc, 0);
+    assert_eq!(fence.borrow().attribute("language"), None);
+    assert_eq!(
+      fence.borrow().attribute("content"),
+      Some(&Value::from("let a = 1;\n"))
+    );
+    assert_eq!(
+      child(&child(&fence, 0), 0).borrow().attribute("content"),
+      Some(&Value::from("let a = 1;\n"))
+    );
+  }
+
+  #[test]
+  fn self_closing_tag_without_slash() {
+    let config = Config {
+      tags: hash!(
+        "img" => Schema {
+          render: "img".into(),
+          self_closing: true,
+          ..Default::default()
+        }
+      ),
+      ..Default::default()
+    };
+
+    let options = ParseOptions {
+      config: Some(&config),
+      ..Default::default()
+    };
+
+    let doc = parse_with_options("{% img %}\n\nThis is a test\n", &options);
+    let img = child(&doc, 0);
+    assert_eq!(img.borrow().children, None);
+    assert_eq!(img.borrow().errors, None);
+    assert_eq!(child(&doc, 1).borrow().kind, Type::Paragraph);
+  }
+
+  #[test]
+  fn else_is_self_closing_without_config() {
+    let doc = parse("{% if $pro %}\nPro\n{% else %}\nFree\n{% /if %}\n");
+    let tag = child(&doc, 0);
+    assert_eq!(tag.borrow().errors, None);
+
+    let kinds: Vec<_> = tag
+      .borrow()
+      .children
+      .iter()
+      .flatten()
+      .map(|child| (child.borrow().kind.clone(), child.borrow().tag.clone()))
+      .collect();
+    assert_eq!(
+      kinds,
+      vec![
+        (Type::Paragraph, None),
+        (Type::Tag(false), Some("else".into())),
+        (Type::Paragraph, None),
+      ]
+    );
+    assert_eq!(child(&tag, 1).borrow().children, None);
+  }
+
+  #[test]
+  fn unclosed_inline_tag_closed_with_paragraph() {
+    let doc = parse("This is {% foo %}a test\n\nAnother paragraph\n");
+    let paragraph = child(&doc, 0);
+    let foo = child(&child(&paragraph, 0), 1);
+    assert_eq!(
+      foo.borrow().errors.as_ref().map(|errors| errors[0].id),
+      Some("missing-closing")
+    );
+    assert_eq!(doc.borrow().children.as_ref().map(|x| x.len()), Some(2));
+    assert_eq!(child(&doc, 1).borrow().kind, Type::Paragraph);
+  }
+
+  fn partial_config<'a>() -> Config<'a> {
+    Config {
+      tags: crate::schema::default_tags(),
+      partials: Some(Box::new(hash!(
+        "header.md" => "# Header\n\n{% partial file=\"nested.md\" /%}\n",
+        "nested.md" => "Nested *text*\n",
+        "loop.md" => "{% partial file=\"loop.md\" /%}\n",
+        "invalid.md" => "---\ntitle: [\n---\n\nText\n"
+      ))),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn partial_grafted_with_own_locations() {
+    let config = partial_config();
+    let options = ParseOptions {
+      config: Some(&config),
+      ..Default::default()
+    };
+
+    let doc = parse_with_options("Intro\n\n{% partial file=\"header.md\" /%}\n", &options);
+    let partial = child(&doc, 1);
+    let heading = child(&partial, 0);
+    assert_eq!(heading.borrow().kind, Type::Heading);
+
+    let location = heading.borrow().location.clone().unwrap();
+    assert_eq!(location.file.as_deref(), Some("header.md"));
+    assert_eq!(location.range(), 0..9);
+
+    let nested = child(&child(&partial, 1), 0);
+    let location = nested.borrow().location.clone().unwrap();
+    assert_eq!(nested.borrow().kind, Type::Paragraph);
+    assert_eq!(location.file.as_deref(), Some("nested.md"));
+    assert_eq!(partial.borrow().location.as_ref().unwrap().file, None);
+  }
+
+  #[test]
+  fn partial_errors() {
+    let config = partial_config();
+    let options = ParseOptions {
+      config: Some(&config),
+      ..Default::default()
+    };
+
+    let doc = parse_with_options("{% partial file=\"missing.md\" /%}\n", &options);
+    let partial = child(&doc, 0);
+    assert_eq!(
+      partial.borrow().errors.as_ref().map(|errors| errors[0].id),
+      Some("partial-not-found")
+    );
+
+    let doc = parse_with_options("{% partial file=\"loop.md\" /%}\n", &options);
+    let inner = child(&child(&doc, 0), 0);
+    let errors = inner.borrow_mut().errors.take().unwrap();
+    assert_eq!(errors[0].id, "partial-cycle");
+    assert_eq!(errors[0].message, "Partial 'loop.md' includes itself");
+    assert_eq!(
+      errors[0].location.as_ref().and_then(|x| x.file.as_deref()),
+      Some("loop.md")
+    );
+
+    let doc = parse_with_options("{% partial file=$name /%}\n", &options);
+    let errors = child(&doc, 0).borrow_mut().errors.take().unwrap();
+    assert_eq!(errors[0].id, "partial-file-invalid");
+  }
+
+  #[test]
+  fn partial_document_errors() {
+    let config = partial_config();
+    let options = ParseOptions {
+      config: Some(&config),
+      frontmatter: true,
+    };
+
+    let doc = parse_with_options("{% partial file=\"invalid.md\" /%}\n", &options);
+    let partial = child(&doc, 0);
+    let errors = partial.borrow_mut().errors.take().unwrap();
+    assert_eq!(errors[0].id, "invalid-frontmatter");
+    assert_eq!(
+      errors[0].location.as_ref().and_then(|x| x.file.as_deref()),
+      Some("invalid.md")
+    );
+    assert_eq!(child(&partial, 0).borrow().kind, Type::Paragraph);
+  }
 }
@@ -0,0 +1,136 @@
+use crate::model::schema::PartialProvider;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::hash::Hash;
+use std::path::Path;
+use std::{fs, io};
+
+impl<K, V> PartialProvider for HashMap<K, V>
+where
+  K: Borrow<str> + Hash + Eq,
+  V: AsRef<str>,
+{
+  fn get(&self, file: &str) -> Option<&str> {
+    HashMap::get(self, file).map(AsRef::as_ref)
+  }
+}
+
+// Loads every `.md` file below a directory up front, keyed by its path
+// relative to the directory with `/` separators, so partial sources outlive
+// the tree. Symlinked files are read but symlinked directories aren't
+// followed, so a link back up the tree can't recurse forever. Any file that
+// can't be read as UTF-8 text fails the whole load.
+#[derive(Debug, Default)]
+pub struct DirectoryPartials {
+  files: HashMap<String, String>,
+}
+
+impl DirectoryPartials {
+  pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    let mut files = HashMap::new();
+    load_directory(fs::read_dir(path)?, "", &mut files)?;
+    Ok(DirectoryPartials { files })
+  }
+}
+
+fn load_directory(
+  entries: fs::ReadDir,
+  prefix: &str,
+  files: &mut HashMap<String, String>,
+) -> io::Result<()> {
+  for entry in entries {
+    let entry = entry?;
+    let path = entry.path();
+    let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+
+    if entry.file_type()?.is_dir() {
+      load_directory(fs::read_dir(&path)?, &format!("{}/", name), files)?;
+    } else if path.extension() == Some(OsStr::new("md")) && path.is_file() {
+      files.insert(name, fs::read_to_string(&path)?);
+    }
+  }
+
+  Ok(())
+}
+
+impl PartialProvider for DirectoryPartials {
+  fn get(&self, file: &str) -> Option<&str> {
+    self.files.get(file).map(String::as_str)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn map_partials() {
+    let partials = hash!("header.md" => "# Header");
+    assert_eq!(
+      PartialProvider::get(&partials, "header.md"),
+      Some("# Header")
+    );
+    assert_eq!(PartialProvider::get(&partials, "footer.md"), None);
+  }
+
+  #[test]
+  fn directory_partials() -> io::Result<()> {
+    let root = temp_dir("load");
+    fs::create_dir_all(root.join("shared"))?;
+    fs::write(root.join("header.md"), "# Header")?;
+    fs::write(root.join("shared").join("footer.md"), "Footer")?;
+    fs::write(root.join("logo.png"), [0x89, 0x50, 0x4e, 0x47])?;
+
+    let partials = DirectoryPartials::load(&root);
+    fs::remove_dir_all(&root)?;
+
+    let partials = partials?;
+    assert_eq!(partials.get("header.md"), Some("# Header"));
+    assert_eq!(partials.get("shared/footer.md"), Some("Footer"));
+    assert_eq!(partials.get("missing.md"), None);
+    assert_eq!(partials.get("logo.png"), None);
+    Ok(())
+  }
+
+  #[test]
+  fn directory_partials_invalid_file() -> io::Result<()> {
+    let root = temp_dir("invalid");
+    fs::create_dir_all(&root)?;
+    fs::write(root.join("binary.md"), [0xff, 0xfe, 0x00])?;
+
+    let partials = DirectoryPartials::load(&root);
+    fs::remove_dir_all(&root)?;
+
+    assert_eq!(
+      partials.map(|_| ()).map_err(|error| error.kind()),
+      Err(io::ErrorKind::InvalidData)
+    );
+    Ok(())
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn directory_partials_symlinks() -> io::Result<()> {
+    use std::os::unix::fs::symlink;
+
+    let root = temp_dir("symlinks");
+    fs::create_dir_all(root.join("shared"))?;
+    fs::write(root.join("shared").join("footer.md"), "Footer")?;
+    symlink(&root, root.join("shared").join("parent"))?;
+    symlink(root.join("shared").join("footer.md"), root.join("link.md"))?;
+
+    let partials = DirectoryPartials::load(&root);
+    fs::remove_dir_all(&root)?;
+
+    let partials = partials?;
+    assert_eq!(partials.get("shared/footer.md"), Some("Footer"));
+    assert_eq!(partials.get("link.md"), Some("Footer"));
+    assert_eq!(partials.get("shared/parent/link.md"), None);
+    Ok(())
+  }
+
+  fn temp_dir(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("markdoc-partials-{}-{}", name, std::process::id()))
+  }
+}
@@ -1,15 +1,91 @@
-use crate::model::render::Renderable;
-use std::io::{Error, Write};
+use super::BLOCK_ELEMENTS;
+use crate::model::{render::Renderable, value::Value, Attributes};
+use std::io::{Error, ErrorKind, Write};
 
 static VOID_ELEMENTS: &[&str] = &[
   "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
   "track", "wbr",
 ];
 
+fn valid_name(name: &str) -> bool {
+  let mut chars = name.chars();
+  matches!(chars.next(), Some(ch) if ch.is_ascii_alphabetic())
+    && chars.all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | ':' | '.'))
+}
+
+fn class_list(value: &Value) -> String {
+  match value {
+    Value::Hash(classes) => classes
+      .iter()
+      .filter(|(_, enabled)| enabled.truthy())
+      .map(|(class, _)| class.to_string())
+      .collect::<Vec<_>>()
+      .join(" "),
+    Value::Array(classes) => classes
+      .iter()
+      .filter(|class| class.truthy())
+      .map(class_list)
+      .filter(|class| !class.is_empty())
+      .collect::<Vec<_>>()
+      .join(" "),
+    Value::Expression(value, _) => class_list(&value.borrow()),
+    value if !value.truthy() => String::new(),
+    value => value.to_string(),
+  }
+}
+
+fn escape<W: Write>(value: &str, writer: &mut W, attribute: bool) -> Result<(), Error> {
+  let bytes = value.as_bytes();
+  let mut last = 0;
+
+  for (index, byte) in bytes.iter().enumerate() {
+    let entity = match byte {
+      b'&' => "&amp;",
+      b'<' => "&lt;",
+      b'>' => "&gt;",
+      b'"' if attribute => "&quot;",
+      _ => continue,
+    };
+
+    writer.write_all(&bytes[last..index])?;
+    writer.write_all(entity.as_bytes())?;
+    last = index + 1;
+  }
+
+  writer.write_all(&bytes[last..])
+}
+
+fn open_tag<W: Write>(
+  name: &str,
+  attributes: &Option<Attributes>,
+  writer: &mut W,
+) -> Result<(), Error> {
+  write!(writer, "<{}", name)?;
+
+  if let Some(attrs) = attributes {
+    for (key, value) in attrs.iter().filter(|(key, _)| valid_name(key)) {
+      let value = if key.as_ref() == "class" {
+        match class_list(value) {
+          classes if classes.is_empty() => continue,
+          classes => classes,
+        }
+      } else {
+        value.to_string()
+      };
+
+      write!(writer, " {}=\"", key)?;
+      escape(&value, writer, true)?;
+      write!(writer, "\"")?;
+    }
+  }
+
+  write!(writer, ">")
+}
+
 pub fn render<W: Write>(node: &Renderable, writer: &mut W) -> Result<(), Error> {
   match node {
     Renderable::String(value) => {
-      writer.write(value.as_bytes())?;
+      escape(value, writer, false)?;
     }
 
     Renderable::Fragment(children) => {
@@ -18,20 +94,19 @@ pub fn render<W: Write>(node: &Renderable, writer: &mut W) -> Result<(), Error>
       }
     }
 
+    // Tags with names that can't be written safely only render their content
+    Renderable::Tag { name, children, .. } if !valid_name(name) => {
+      for child in children.iter().flatten() {
+        render(child, writer)?;
+      }
+    }
+
     Renderable::Tag {
       name,
       attributes,
       children,
     } => {
-      write!(writer, "<{}", name)?;
-
-      if let Some(attrs) = attributes {
-        for (key, value) in attrs {
-          write!(writer, r#" {}="{}""#, key, value)?;
-        }
-      }
-
-      write!(writer, ">")?;
+      open_tag(name, attributes, writer)?;
 
       if VOID_ELEMENTS.contains(&name.as_ref()) {
         return Ok(());
@@ -52,15 +127,161 @@ pub fn render<W: Write>(node: &Renderable, writer: &mut W) -> Result<(), Error>
   Ok(())
 }
 
+pub static PAGE_TEMPLATE: &str = "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n</head>\n<body>\n{content}\n</body>\n</html>\n";
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HtmlOptions<'o> {
+  // Written once per level of nesting before each block element. Without it
+  // the output stays on a single line.
+  pub indent: Option<&'o str>,
+  // Markup the output is placed into at `{content}`, like `PAGE_TEMPLATE`
+  // for standalone pages. Rendering fails with `InvalidInput` if the
+  // placeholder is missing.
+  pub template: Option<&'o str>,
+}
+
+pub fn render_with_options<W: Write>(
+  node: &Renderable,
+  writer: &mut W,
+  options: &HtmlOptions,
+) -> Result<(), Error> {
+  let (before, after) = match options.template {
+    Some(template) => template.split_once("{content}").ok_or_else(|| {
+      Error::new(
+        ErrorKind::InvalidInput,
+        "template has no {content} placeholder",
+      )
+    })?,
+    None => ("", ""),
+  };
+
+  writer.write_all(before.as_bytes())?;
+
+  if let Some(indent) = options.indent {
+    let mut pretty = PrettyWriter {
+      writer,
+      indent,
+      depth: 0,
+      started: false,
+      after_block: false,
+    };
+    pretty.render(node)?;
+  } else {
+    render(node, writer)?;
+  }
+
+  writer.write_all(after.as_bytes())
+}
+
+fn has_block(node: &Renderable) -> bool {
+  match node {
+    Renderable::Tag { name, children, .. } if !valid_name(name) => {
+      children.iter().flatten().any(has_block)
+    }
+    Renderable::Tag { name, .. } => BLOCK_ELEMENTS.contains(&name.as_ref()),
+    Renderable::Fragment(children) => children.iter().any(has_block),
+    _ => false,
+  }
+}
+
+// Puts each block element on its own line. Blocks holding only inline content,
+// and `<pre>` whatever it holds, are written as-is since whitespace inside
+// them would change what's displayed. Inline content leading a block that
+// also holds blocks, like the text of a list item with a nested list, stays
+// on the line of the opening tag.
+struct PrettyWriter<'w, W: Write> {
+  writer: &'w mut W,
+  indent: &'w str,
+  depth: usize,
+  started: bool,
+  after_block: bool,
+}
+
+impl<'w, W: Write> PrettyWriter<'w, W> {
+  fn new_line(&mut self) -> Result<(), Error> {
+    if self.started {
+      self.writer.write_all(b"\n")?;
+    }
+
+    self.started = true;
+    for _ in 0..self.depth {
+      self.writer.write_all(self.indent.as_bytes())?;
+    }
+
+    Ok(())
+  }
+
+  fn render(&mut self, node: &Renderable) -> Result<(), Error> {
+    match node {
+      Renderable::Fragment(children) => {
+        for child in children {
+          self.render(child)?;
+        }
+      }
+
+      Renderable::Tag { name, children, .. } if !valid_name(name) => {
+        for child in children.iter().flatten() {
+          self.render(child)?;
+        }
+      }
+
+      Renderable::Tag {
+        name,
+        attributes,
+        children,
+      } if BLOCK_ELEMENTS.contains(&name.as_ref()) => {
+        self.new_line()?;
+
+        if name.as_ref() == "pre" || !children.iter().flatten().any(has_block) {
+          render(node, self.writer)?;
+        } else {
+          open_tag(name, attributes, self.writer)?;
+          self.depth += 1;
+          self.after_block = false;
+
+          for child in children.iter().flatten() {
+            self.render(child)?;
+          }
+
+          self.depth -= 1;
+          self.new_line()?;
+          write!(self.writer, "</{}>", name)?;
+        }
+
+        self.after_block = true;
+      }
+
+      Renderable::Null => (),
+      Renderable::String(value) if value.is_empty() => (),
+
+      node => {
+        if self.after_block {
+          self.new_line()?;
+          self.after_block = false;
+        }
+
+        self.started = true;
+        render(node, self.writer)?;
+      }
+    }
+
+    Ok(())
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
   use crate::{
-    model::schema::{Config, Variables},
-    parse::parse,
+    model::{
+      schema::{Attribute, Config, Schema, Variables},
+      Attributes,
+    },
+    parse::{parse, parse_with_options, ParseOptions},
     schema::default_nodes,
     transform::transform_node,
   };
+  use indexmap::indexmap;
   use std::io::BufWriter;
 
   #[test]
@@ -101,17 +322,12 @@ This is a test: {% $foo.bar %}
     let config = Config {
       tags: config.tags,
       nodes: default_nodes(),
-      variables: Variables::Values(hash!(
-        "foo".into() => hash!(
-          "bar".into() => "variable resolved".into()
-        ).into()
-      ))
-      .into(),
-      functions: None,
+      variables: Variables::Values(mdattrs!(foo = mdattrs!(bar = "variable resolved"))).into(),
+      ..Default::default()
     };
 
     let parent = doc.borrow();
-    crate::resolve::resolve_node(&parent, &config);
+    crate::resolve::resolve_node(&parent, &config).expect("resolves");
     let rendered = transform_node(&*parent, &config);
     let mut writer = BufWriter::new(Vec::new());
     render(&rendered, &mut writer).expect("completes");
@@ -119,4 +335,316 @@ This is a test: {% $foo.bar %}
     let expected = r#"<h1>This is a test</h1><hr><p>This is a sample document</p><ul><li>This is a bulleted list</li><li>With another list item</li></ul><foo bar="test"><p>This is a test: variable resolved</p></foo>"#;
     assert_eq!(Ok(expected), output);
   }
+
+  fn render_string(node: &Renderable) -> String {
+    let mut output = Vec::new();
+    render(node, &mut output).expect("completes");
+    String::from_utf8(output).unwrap()
+  }
+
+  #[test]
+  fn escapes_text() {
+    let doc = parse("This is &lt;script&gt;alert(\"&amp;\")&lt;/script&gt;");
+    let config = Config {
+      nodes: default_nodes(),
+      ..Default::default()
+    };
+
+    let rendered = transform_node(&doc.borrow(), &config);
+    assert_eq!(
+      render_string(&rendered),
+      r#"<p>This is &lt;script&gt;alert("&amp;")&lt;/script&gt;</p>"#
+    );
+  }
+
+  #[test]
+  fn escapes_raw_html() {
+    let doc = parse("<script>alert(1)</script>\n\nSome <img src=x onerror=\"alert(1)\"> text\n");
+    let config = Config {
+      nodes: default_nodes(),
+      ..Default::default()
+    };
+
+    let rendered = transform_node(&doc.borrow(), &config);
+    assert_eq!(
+      render_string(&rendered),
+      concat!(
+        "&lt;script&gt;alert(1)&lt;/script&gt;\n",
+        r#"<p>Some &lt;img src=x onerror="alert(1)"&gt; text</p>"#
+      )
+    );
+  }
+
+  #[test]
+  fn escapes_literal_tag_attributes() {
+    let doc = parse("{% foo bar=\"\\\"><script>alert(1)</script>\" %}\nTest\n{% /foo %}");
+    let config = Config {
+      tags: hash!(
+        "foo" => Schema {
+          render: "foo".into(),
+          attributes: indexmap!("bar" => Attribute::default()).into(),
+          ..Default::default()
+        }
+      ),
+      nodes: default_nodes(),
+      ..Default::default()
+    };
+
+    let rendered = transform_node(&doc.borrow(), &config);
+    assert_eq!(
+      render_string(&rendered),
+      r#"<foo bar="&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;"><p>Test</p></foo>"#
+    );
+  }
+
+  #[test]
+  fn renders_inline_code() {
+    let doc = parse("Use `<b>bold</b> & {% $foo %}` here");
+    let config = Config {
+      nodes: default_nodes(),
+      variables: Variables::Values(mdattrs!(foo = "resolved")).into(),
+      ..Default::default()
+    };
+
+    crate::resolve::resolve_node(&doc.borrow(), &config).expect("resolves");
+    let rendered = transform_node(&doc.borrow(), &config);
+    assert_eq!(
+      render_string(&rendered),
+      "<p>Use <code>&lt;b&gt;bold&lt;/b&gt; &amp; {% $foo %}</code> here</p>"
+    );
+  }
+
+  #[test]
+  fn renders_fences() {
+    let doc = parse(
+      "```js\nif (a < b) {\n  {% $foo %}\n}\n```\n\n```html {% process=false %}\n<p>{% $foo %}</p>\n```\n",
+    );
+    let config = Config {
+      nodes: default_nodes(),
+      variables: Variables::Values(mdattrs!(foo = "resolved")).into(),
+      ..Default::default()
+    };
+
+    crate::resolve::resolve_node(&doc.borrow(), &config).expect("resolves");
+    let rendered = transform_node(&doc.borrow(), &config);
+    assert_eq!(
+      render_string(&rendered),
+      concat!(
+        r#"<pre data-language="js"><code>if (a &lt; b) {"#,
+        "\n  resolved\n}\n</code></pre>",
+        r#"<pre data-language="html"><code>&lt;p&gt;{% $foo %}&lt;/p&gt;"#,
+        "\n</code></pre>"
+      )
+    );
+  }
+
+  #[test]
+  fn renders_frontmatter_variables() {
+    let options = ParseOptions {
+      frontmatter: true,
+      ..Default::default()
+    };
+
+    let doc = parse_with_options(
+      "---\npage:\n  title: Example\n---\n\n# {% $page.title %}\n",
+      &options,
+    );
+    let config = Config {
+      nodes: default_nodes(),
+      variables: doc.borrow().frontmatter().cloned().map(Variables::Values),
+      ..Default::default()
+    };
+
+    crate::resolve::resolve_node(&doc.borrow(), &config).expect("resolves");
+    let rendered = transform_node(&doc.borrow(), &config);
+    assert_eq!(render_string(&rendered), "<h1>Example</h1>");
+  }
+
+  #[test]
+  fn escapes_attribute_values() {
+    let doc = parse("{% foo bar=$attack %}\nTest\n{% /foo %}");
+    let config = Config {
+      tags: hash!(
+        "foo" => Schema {
+          render: "foo".into(),
+          attributes: indexmap!("bar" => Attribute::default()).into(),
+          ..Default::default()
+        }
+      ),
+      nodes: default_nodes(),
+      variables: Variables::Values(mdattrs!(attack = "\"><script>alert(1)</script>")).into(),
+      ..Default::default()
+    };
+
+    crate::resolve::resolve_node(&doc.borrow(), &config).expect("resolves");
+    let rendered = transform_node(&doc.borrow(), &config);
+    assert_eq!(
+      render_string(&rendered),
+      r#"<foo bar="&quot;&gt;&lt;script&gt;alert(1)&lt;/script&gt;"><p>Test</p></foo>"#
+    );
+  }
+
+  #[test]
+  fn renders_attributes_in_schema_order() {
+    let doc = parse("{% foo c=3 a=1 b=2 /%}");
+    let config = Config {
+      tags: hash!(
+        "foo" => Schema {
+          render: "foo".into(),
+          attributes: indexmap!(
+            "b" => Attribute::default(),
+            "c" => Attribute::default(),
+            "a" => Attribute::default()
+          )
+          .into(),
+          ..Default::default()
+        }
+      ),
+      nodes: default_nodes(),
+      ..Default::default()
+    };
+
+    let rendered = transform_node(&doc.borrow(), &config);
+    assert_eq!(render_string(&rendered), r#"<foo b="2" c="3" a="1"></foo>"#);
+  }
+
+  #[test]
+  fn renders_class_list() {
+    let doc = parse(
+      "{% foo .first class={active: $active, inactive: $inactive} .last %}\nTest {% .inline %}\n{% /foo %}",
+    );
+    let config = Config {
+      tags: hash!(
+        "foo" => Schema {
+          render: "foo".into(),
+          ..Default::default()
+        }
+      ),
+      nodes: default_nodes(),
+      variables: Variables::Values(mdattrs!(active = 0, inactive = false)).into(),
+      ..Default::default()
+    };
+
+    crate::resolve::resolve_node(&doc.borrow(), &config).expect("resolves");
+    let rendered = transform_node(&doc.borrow(), &config);
+    assert_eq!(
+      render_string(&rendered),
+      r#"<foo class="first active last"><p class="inline">Test </p></foo>"#
+    );
+  }
+
+  #[test]
+  fn skips_invalid_names() {
+    let node = Renderable::Tag {
+      name: "script><img".into(),
+      attributes: None,
+      children: vec![Renderable::Tag {
+        name: "span".into(),
+        attributes: Attributes::from([
+          ("onclick=\"alert(1)\" x".into(), "test".into()),
+          ("title".into(), "test".into()),
+        ])
+        .into(),
+        children: vec![Renderable::String("content".into())].into(),
+      }]
+      .into(),
+    };
+
+    assert_eq!(render_string(&node), r#"<span title="test">content</span>"#);
+  }
+
+  fn render_pretty(input: &str, options: &HtmlOptions) -> String {
+    let doc = parse(input);
+    let config = Config {
+      nodes: default_nodes(),
+      tags: hash!(
+        "section" => Schema {
+          render: "section".into(),
+          ..Default::default()
+        }
+      ),
+      ..Default::default()
+    };
+
+    let rendered = transform_node(&doc.borrow(), &config);
+    let mut output = Vec::new();
+    render_with_options(&rendered, &mut output, options).expect("completes");
+    String::from_utf8(output).unwrap()
+  }
+
+  #[test]
+  fn renders_indented() {
+    let options = HtmlOptions {
+      indent: Some("  "),
+      ..Default::default()
+    };
+
+    let output = render_pretty(
+      "# Title\n\n{% section %}\nSome *inline* text\n\n- One\n- Two\n\n{% /section %}\n\n---\n\n```\nline one\n  line two\n```\n",
+      &options,
+    );
+
+    assert_eq!(
+      output,
+      concat!(
+        "<h1>Title</h1>\n",
+        "<section>\n",
+        "  <p>Some <em>inline</em> text</p>\n",
+        "  <ul>\n",
+        "    <li>One</li>\n",
+        "    <li>Two</li>\n",
+        "  </ul>\n",
+        "</section>\n",
+        "<hr>\n",
+        "<pre><code>line one\n  line two\n</code></pre>"
+      )
+    );
+  }
+
+  #[test]
+  fn renders_mixed_blocks_indented() {
+    let options = HtmlOptions {
+      indent: Some("  "),
+      ..Default::default()
+    };
+
+    assert_eq!(
+      render_pretty("- One\n  - Nested\n- Two\n", &options),
+      concat!(
+        "<ul>\n",
+        "  <li>One\n",
+        "    <ul>\n",
+        "      <li>Nested</li>\n",
+        "    </ul>\n",
+        "  </li>\n",
+        "  <li>Two</li>\n",
+        "</ul>"
+      )
+    );
+  }
+
+  #[test]
+  fn template_requires_content() {
+    let options = HtmlOptions {
+      template: Some("<main></main>"),
+      ..Default::default()
+    };
+
+    let mut output = Vec::new();
+    let error = render_with_options(&Renderable::Null, &mut output, &options).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidInput);
+  }
+
+  #[test]
+  fn renders_page_template() {
+    let options = HtmlOptions {
+      template: Some(PAGE_TEMPLATE),
+      ..Default::default()
+    };
+
+    assert_eq!(
+      render_pretty("Hello", &options),
+      "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n</head>\n<body>\n<p>Hello</p>\n</body>\n</html>\n"
+    );
+  }
 }
@@ -0,0 +1,247 @@
+use super::BLOCK_ELEMENTS;
+use crate::model::render::Renderable;
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TextOptions {
+  // Cuts the text down to at most this many characters, including the
+  // trailing ellipsis, at the last word boundary that fits. A first word
+  // that doesn't fit is cut at the limit instead.
+  pub max_length: Option<usize>,
+}
+
+pub fn render(node: &Renderable) -> String {
+  render_with_options(node, &TextOptions::default())
+}
+
+pub fn render_with_options(node: &Renderable, options: &TextOptions) -> String {
+  let mut writer = TextWriter::default();
+  writer.write(node);
+
+  let text = writer.output.trim_end();
+  match options.max_length {
+    Some(length) => truncate(text, length),
+    None => text.to_string(),
+  }
+}
+
+fn truncate(text: &str, length: usize) -> String {
+  let limit = match text.char_indices().nth(length) {
+    Some(_) if length == 0 => return String::new(),
+    Some(_) => text
+      .char_indices()
+      .nth(length - 1)
+      .map_or(0, |(index, _)| index),
+    None => return text.to_string(),
+  };
+
+  let (head, rest) = text.split_at(limit);
+  let head = if rest.starts_with(char::is_whitespace) {
+    head
+  } else {
+    match head.rfind(char::is_whitespace) {
+      Some(index) if !head[..index].trim_end().is_empty() => &head[..index],
+      _ => head,
+    }
+  };
+
+  format!("{}…", head.trim_end())
+}
+
+#[derive(Default)]
+struct TextWriter {
+  output: String,
+  // One entry per open list, holding the next number for ordered lists
+  lists: Vec<Option<usize>>,
+  bullet: Option<String>,
+}
+
+impl TextWriter {
+  // Blank lines only separate top-level blocks so list items stay together
+  fn line_break(&mut self, blank: bool) {
+    let end = self.output.trim_end_matches(' ').len();
+    self.output.truncate(end);
+
+    if self.output.is_empty() {
+      return;
+    }
+
+    let wanted = if blank && self.lists.is_empty() { 2 } else { 1 };
+    let newlines = self.output.len() - self.output.trim_end_matches('\n').len();
+    for _ in newlines..wanted {
+      self.output.push('\n');
+    }
+  }
+
+  fn write_text(&mut self, text: &str) {
+    if let Some(bullet) = self.bullet.take() {
+      self.output.push_str(&bullet);
+    }
+
+    self.output.push_str(text);
+  }
+
+  fn write_children(&mut self, children: &Option<Vec<Renderable>>) {
+    for child in children.iter().flatten() {
+      self.write(child);
+    }
+  }
+
+  fn write(&mut self, node: &Renderable) {
+    match node {
+      Renderable::String(value) => self.write_text(value),
+
+      Renderable::Fragment(children) => {
+        for child in children {
+          self.write(child);
+        }
+      }
+
+      Renderable::Tag {
+        name,
+        attributes,
+        children,
+      } => match name.as_ref() {
+        "br" => self.output.push('\n'),
+
+        "img" => {
+          if let Some(alt) = attributes.as_ref().and_then(|attrs| attrs.get("alt")) {
+            self.write_text(&alt.to_string());
+          }
+        }
+
+        "ul" | "ol" => {
+          self.line_break(true);
+          self.lists.push((name.as_ref() == "ol").then_some(1));
+          self.write_children(children);
+          self.lists.pop();
+          self.line_break(true);
+        }
+
+        "li" => {
+          self.line_break(false);
+          let mut bullet = "  ".repeat(self.lists.len().saturating_sub(1));
+          match self.lists.last_mut() {
+            Some(Some(number)) => {
+              let _ = write!(bullet, "{}. ", number);
+              *number += 1;
+            }
+            _ => bullet.push_str("- "),
+          }
+
+          self.bullet = Some(bullet);
+          self.write_children(children);
+          self.bullet = None;
+          self.line_break(false);
+        }
+
+        // Row groups sit inside a table that's already on its own lines
+        "thead" | "tbody" | "tfoot" => self.write_children(children),
+
+        "tr" => {
+          self.line_break(false);
+          self.write_children(children);
+          self.line_break(false);
+        }
+
+        "td" | "th" => {
+          if !self.output.is_empty() && !self.output.ends_with(['\n', ' ']) {
+            self.output.push(' ');
+          }
+          self.write_children(children);
+        }
+
+        name if BLOCK_ELEMENTS.contains(&name) => {
+          self.line_break(true);
+          self.write_children(children);
+          self.line_break(true);
+        }
+
+        _ => self.write_children(children),
+      },
+
+      Renderable::Null => (),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{
+    model::schema::Config, parse::parse, schema::default_nodes, transform::transform_node,
+  };
+
+  fn render_text(input: &str, options: &TextOptions) -> String {
+    let config = Config {
+      nodes: default_nodes(),
+      ..Default::default()
+    };
+
+    let doc = parse(input);
+    let rendered = transform_node(&doc.borrow(), &config);
+    render_with_options(&rendered, options)
+  }
+
+  #[test]
+  fn renders_blocks() {
+    let input = "# Getting *started*\n\nInstall the **CLI** with `npm`.\n\n- One\n- Two\n  1. Nested\n  2. Items\n\n---\n\n> Quoted text\n";
+    assert_eq!(
+      render_text(input, &TextOptions::default()),
+      "Getting started\n\nInstall the CLI with npm.\n\n- One\n- Two\n  1. Nested\n  2. Items\n\nQuoted text"
+    );
+  }
+
+  #[test]
+  fn renders_tables_and_fences() {
+    let input = "| a | b |\n|---|---|\n| c | d |\n\n```sh\nnpm install\nnpm test\n```\n";
+    assert_eq!(
+      render_text(input, &TextOptions::default()),
+      "a b\nc d\n\nnpm install\nnpm test"
+    );
+  }
+
+  #[test]
+  fn renders_html_blocks() {
+    let tag = |name: &'static str, children| Renderable::Tag {
+      name: name.into(),
+      attributes: None,
+      children: Some(children),
+    };
+    let text = |text: &'static str| Renderable::String(text.into());
+
+    let node = Renderable::Fragment(vec![
+      tag(
+        "details",
+        vec![tag("summary", vec![text("More")]), text("Details")],
+      ),
+      tag(
+        "dl",
+        vec![
+          tag("dt", vec![text("Term")]),
+          tag("dd", vec![text("Definition")]),
+        ],
+      ),
+      tag("figure", vec![tag("figcaption", vec![text("Caption")])]),
+    ]);
+
+    assert_eq!(
+      render(&node),
+      "More\n\nDetails\n\nTerm\n\nDefinition\n\nCaption"
+    );
+  }
+
+  #[test]
+  fn truncates_at_word_boundary() {
+    let input = "Markdoc is a powerful, flexible, Markdown-based authoring framework.";
+    let render = |max_length| render_text(input, &TextOptions { max_length });
+
+    assert_eq!(render(Some(20)), "Markdoc is a…");
+    assert_eq!(render(Some(24)), "Markdoc is a powerful,…");
+    assert_eq!(render(Some(3)), "Ma…");
+    assert_eq!(render(Some(8)), "Markdoc…");
+    assert_eq!(render(Some(1)), "…");
+    assert_eq!(render(Some(0)), "");
+    assert_eq!(render(Some(100)), input);
+  }
+}
@@ -1,101 +1,378 @@
+use crate::functions;
 use crate::model::{
-  schema::{Config, Variables},
-  value::{Expression, Value},
-  Attributes, node::Node,
+  location::Location,
+  node::{Error, ErrorLevel, Node, Type},
+  schema::{Attribute, Config, Variables},
+  value::{Expression, Value, ValueRef},
+  Attributes,
 };
+use crate::validate::type_matches;
+use indexmap::IndexMap;
 
-pub fn resolve_attributes<'a>(attributes: &Attributes<'a>, config: &'a Config<'a>) {
-  for (_, value) in attributes {
-    resolve(value, config)
-  }
+// Unresolved references are reported as warnings, or as errors that fail the
+// resolution when the config is strict
+pub type Diagnostics = Result<Vec<Error>, Vec<Error>>;
+
+pub fn resolve_attributes<'a>(attributes: &Attributes<'a>, config: &'a Config<'a>) -> Diagnostics {
+  let mut resolver = Resolver::new(config);
+  resolver.attributes(attributes);
+  resolver.finish()
+}
+
+pub fn resolve<'a>(value: &Value<'a>, config: &'a Config<'a>) -> Diagnostics {
+  let mut resolver = Resolver::new(config);
+  resolver.value(value);
+  resolver.finish()
+}
+
+pub fn resolve_node<'a>(node: &Node<'a>, config: &'a Config<'a>) -> Diagnostics {
+  let mut resolver = Resolver::new(config);
+  resolver.node(node);
+  resolver.finish()
 }
 
-pub fn resolve<'a>(value: &Value<'a>, config: &'a Config<'a>) {
-  match value {
-    Value::Hash(attributes) => resolve_attributes(attributes, config),
+struct Resolver<'a> {
+  config: &'a Config<'a>,
+  // Variables introduced by enclosing nodes, innermost last, which shadow the
+  // config variables. `special` holds the engine-provided `@` variables.
+  scopes: Vec<Attributes<'a>>,
+  special: Vec<Attributes<'a>>,
+  location: Option<Location>,
+  errors: Vec<Error>,
+}
+
+impl<'a> Resolver<'a> {
+  fn new(config: &'a Config<'a>) -> Self {
+    Resolver {
+      config,
+      scopes: Vec::new(),
+      special: Vec::new(),
+      location: None,
+      errors: Vec::new(),
+    }
+  }
+
+  fn finish(self) -> Diagnostics {
+    if self.config.strict && !self.errors.is_empty() {
+      Err(self.errors)
+    } else {
+      Ok(self.errors)
+    }
+  }
+
+  fn report(&mut self, id: &'static str, message: String) {
+    let level = if self.config.strict {
+      ErrorLevel::Error
+    } else {
+      ErrorLevel::Warning
+    };
+
+    self.report_level(id, level, message);
+  }
+
+  fn report_level(&mut self, id: &'static str, level: ErrorLevel, message: String) {
+    self.errors.push(Error {
+      id,
+      level,
+      message,
+      location: self.location.clone(),
+    });
+  }
+
+  fn attributes(&mut self, attributes: &Attributes<'a>) {
+    for (_, value) in attributes {
+      self.value(value)
+    }
+  }
 
-    Value::Array(values) => {
-      for value in values {
-        resolve(value, config)
+  fn value(&mut self, value: &Value<'a>) {
+    match value {
+      Value::Hash(attributes) => self.attributes(attributes),
+
+      Value::Array(values) => {
+        for value in values {
+          self.value(value)
+        }
+      }
+
+      Value::Expression(value, Expression::Function(name, parameters, location)) => {
+        // Reports inside the call point at the call rather than its tag
+        let outer = self.location.clone();
+        if location.is_some() {
+          self.location = location.clone();
+        }
+
+        let schema = self
+          .config
+          .functions
+          .as_ref()
+          .and_then(|fns| fns.get(name.as_ref()));
+
+        let evaluate = schema
+          .map(|f| f.evaluate.clone())
+          .or_else(|| functions::builtin(name));
+
+        // The built-in `default` exists to handle missing variables, so the
+        // variables passed straight to it aren't reported
+        let fallback = schema.is_none() && name.as_ref() == "default";
+
+        *value.borrow_mut() = match evaluate {
+          Some(evaluate) => {
+            for (_, parameter) in parameters {
+              match parameter {
+                Value::Expression(resolved, Expression::Variable(sigil, path)) if fallback => {
+                  self.variable_expression(resolved, *sigil, path, false)
+                }
+                parameter => self.value(parameter),
+              }
+            }
+
+            let declared = schema.and_then(|f| f.attributes.as_ref());
+            if self.parameters(name, parameters, declared) {
+              evaluate(parameters, self.config)
+            } else {
+              Value::Undefined
+            }
+          }
+          None => {
+            self.report(
+              "undefined-function",
+              format!("Undefined function: '{}'", name),
+            );
+            Value::Undefined
+          }
+        };
+
+        self.location = outer;
       }
+
+      Value::Expression(value, Expression::Variable(sigil, path)) => {
+        self.variable_expression(value, *sigil, path, true)
+      }
+
+      _ => (),
     }
+  }
 
-    Value::Expression(value, Expression::Function(name, parameters)) => {
-      *value.borrow_mut() = config
-        .functions
-        .as_ref()
-        .and_then(|fns| fns.get(name.as_ref()))
-        .map_or(Value::Undefined, |f| {
-          resolve_attributes(parameters, config);
-          (f.evaluate)(parameters, config)
-        })
+  // Only the variable itself goes unreported when `report` is off, since
+  // segments like the `$index` in `$items[$index]` are looked up first
+  fn variable_expression(
+    &mut self,
+    value: &ValueRef<'a>,
+    sigil: char,
+    path: &[Value<'a>],
+    report: bool,
+  ) {
+    for segment in path {
+      self.value(segment);
     }
 
-    Value::Expression(value, Expression::Variable(_, path)) => {
-      *value.borrow_mut() = match &config.variables {
-        Some(Variables::Resolver(vfn)) => Some(vfn(&path[..])),
-        Some(Variables::Values(variables)) => match &path[..] {
-          [Value::String(first)] => variables.get(first).map(|x| x.clone()),
-          [Value::String(first), rest @ ..] => variables
-            .get(first)
-            .and_then(|vars| vars.deep_get(rest))
-            .map(|x| x.clone()),
-          _ => None,
-        },
+    let lookup: Vec<_> = path.iter().map(|x| x.resolved(Value::clone)).collect();
+    let resolved = self.variable(sigil, &lookup);
+
+    if report && matches!(resolved, Value::Undefined) {
+      let message = format!("Undefined variable: '{}'", variable_name(sigil, path));
+      self.report("undefined-variable", message);
+    }
+
+    *value.borrow_mut() = resolved;
+  }
+
+  // Checks call arguments against the function schema, where positional
+  // parameters are declared with the "0", "1", ... keys the tag parser uses.
+  // Invalid calls never run, so they're errors even outside strict mode.
+  fn parameters(
+    &mut self,
+    name: &str,
+    parameters: &Attributes<'a>,
+    declared: Option<&IndexMap<&str, Attribute>>,
+  ) -> bool {
+    let declared = match declared {
+      Some(declared) => declared,
+      None => return true,
+    };
+
+    let errors = self.errors.len();
+    let positional = |key: &str| key.parse::<usize>().is_ok();
+    let expected = declared.keys().filter(|key| positional(key)).count();
+    let given = parameters.keys().filter(|key| positional(key)).count();
+
+    if given > expected {
+      let message = format!(
+        "Function '{}' takes {} positional parameters but {} were given",
+        name, expected, given
+      );
+      self.report_level("parameter-count-invalid", ErrorLevel::Error, message);
+    }
+
+    for (key, value) in parameters {
+      match declared.get(key.as_ref()) {
+        Some(Attribute {
+          kind: Some(kind), ..
+        }) if !type_matches(kind, value) => {
+          let message = format!(
+            "Parameter '{}' of '{}' must be type of '{:?}'",
+            key, name, kind
+          );
+          self.report_level("parameter-type-invalid", ErrorLevel::Error, message);
+        }
+        Some(_) => (),
+        None if positional(key) => (),
+        None => {
+          let message = format!("Invalid parameter '{}' for function '{}'", key, name);
+          self.report_level("parameter-undefined", ErrorLevel::Error, message);
+        }
+      }
+    }
+
+    for (key, attr) in declared {
+      if attr.required && !parameters.contains_key(*key) {
+        let message = format!(
+          "Missing required parameter '{}' for function '{}'",
+          key, name
+        );
+        self.report_level("parameter-missing-required", ErrorLevel::Error, message);
+      }
+    }
+
+    self.errors.len() == errors
+  }
+
+  fn variable(&self, sigil: char, path: &[Value<'a>]) -> Value<'a> {
+    let (scopes, variables) = match sigil {
+      '@' => (&self.special, &self.config.special_variables),
+      _ => (&self.scopes, &self.config.variables),
+    };
+
+    let scoped = match path {
+      [Value::String(first), rest @ ..] => scopes
+        .iter()
+        .rev()
+        .find_map(|scope| scope.get(first))
+        .map(|value| value.deep_get(rest).cloned()),
+      _ => None,
+    };
+
+    match (scoped, variables) {
+      (Some(scoped), _) => scoped,
+      (None, Some(Variables::Resolver(vfn))) => Some(vfn(path)),
+      (None, Some(Variables::Values(variables))) => match path {
+        [Value::String(first)] => variables.get(first).map(|x| x.clone()),
+        [Value::String(first), rest @ ..] => variables
+          .get(first)
+          .and_then(|vars| vars.deep_get(rest))
+          .map(|x| x.clone()),
         _ => None,
+      },
+      _ => None,
+    }
+    .unwrap_or(Value::Undefined)
+  }
+
+  fn node(&mut self, node: &Node<'a>) {
+    if let Some(attributes) = &node.attributes {
+      self.location = node.location.clone();
+      self.attributes(attributes);
+    }
+
+    let partial = matches!(
+      (&node.kind, node.tag.as_deref()),
+      (Type::Tag(..), Some("partial"))
+    );
+
+    // Partials see their `variables` attribute on top of the enclosing scope,
+    // and the engine provides `@file` for the partial being rendered
+    let (scope, special) = if partial {
+      let scope = node.attribute("variables").and_then(|variables| {
+        variables.resolved(|value| match value {
+          Value::Hash(variables) => Some(
+            variables
+              .iter()
+              .map(|(key, value)| (key.clone(), value.resolved(Value::clone)))
+              .collect(),
+          ),
+          _ => None,
+        })
+      });
+
+      let special = node
+        .attribute("file")
+        .map(|file| Attributes::from([("file".into(), file.resolved(Value::clone))]));
+
+      (scope, special)
+    } else {
+      (None, None)
+    };
+
+    let (scoped, special_scoped) = (scope.is_some(), special.is_some());
+    self.scopes.extend(scope);
+    self.special.extend(special);
+
+    if let Some(children) = &node.children {
+      for child in children {
+        self.node(&child.borrow())
       }
-      .unwrap_or(Value::Undefined)
     }
 
-    _ => (),
+    if scoped {
+      self.scopes.pop();
+    }
+
+    if special_scoped {
+      self.special.pop();
+    }
   }
 }
 
-pub fn resolve_node<'a>(node: &Node<'a>, config: &'a Config<'a>) {
-  if let Some(attributes) = &node.attributes {
-    resolve_attributes(attributes, config);
-  }
+fn variable_name(sigil: char, path: &[Value]) -> String {
+  let mut name = sigil.to_string();
 
-  if let Some(children) = &node.children {
-    for child in children {
-      resolve_node(&child.borrow(), config)
+  for (index, segment) in path.iter().enumerate() {
+    match segment {
+      Value::String(key) if index == 0 => name.push_str(key),
+      Value::String(key) => name.push_str(&format!(".{}", key)),
+      Value::Expression(_, Expression::Variable(sigil, path)) => {
+        name.push_str(&format!("[{}]", variable_name(*sigil, path)))
+      }
+      segment => name.push_str(&format!("[{}]", segment)),
     }
   }
+
+  name
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
-  use crate::model::schema::FunctionSchema;
+  use crate::model::location::LineIndex;
+  use crate::model::schema::{evaluate_fn, variable_fn, FunctionSchema, SchemaType};
   use crate::model::value::Expression;
+  use crate::parse::parse;
+  use indexmap::indexmap;
 
   #[test]
   fn example() -> Result<(), std::io::Error> {
     let config = Config {
       tags: hash!(),
       nodes: hash!(),
-      variables: Variables::Values(hash!(
-        "foo".into() => hash!(
-          "bar".into() => "variable resolved".into()
-        ).into()
-      ))
-      .into(),
+      variables: Variables::Values(mdattrs!(foo = mdattrs!(bar = "variable resolved"))).into(),
       functions: hash!(
         "foo" => FunctionSchema {
           attributes: None,
-          evaluate: |_attrs, _config| "function resolved".into(),
+          evaluate: evaluate_fn(|_attrs, _config| "function resolved".into()),
         }
       )
       .into(),
+      ..Default::default()
     };
 
     let array = Value::Array(vec![
       "test".into(),
       Expression::Variable('$', vec!["foo".into(), "bar".into()]).into(),
-      Expression::Function("foo".into(), mdattrs!(example = "test")).into(),
+      Expression::Function("foo".into(), mdattrs!(example = "test"), None).into(),
     ]);
 
-    resolve(&array, &config);
+    resolve(&array, &config).expect("resolves");
 
     match &array {
       Value::Array(values) => match &values.as_slice() {
@@ -115,4 +392,281 @@ mod tests {
       )),
     }
   }
+
+  #[test]
+  fn resolver_captures_state() {
+    let locale = String::from("fr");
+    let config = Config {
+      variables: Variables::Resolver(variable_fn(move |path| match path {
+        [Value::String(name)] if name.as_ref() == "locale" => locale.clone().into(),
+        _ => Value::Undefined,
+      }))
+      .into(),
+      ..Default::default()
+    };
+
+    let value: Value = Expression::Variable('$', vec!["locale".into()]).into();
+    resolve(&value, &config).expect("resolves");
+    assert_eq!(value.resolved(Value::clone), Value::from("fr"));
+  }
+
+  #[test]
+  fn undefined_references_are_reported() {
+    let config = Config {
+      variables: Variables::Values(mdattrs!(page = mdattrs!(title = "Example"))).into(),
+      ..Default::default()
+    };
+
+    let input = "# {% $page.title %}\n\nBy {% $page.author %} on {% today() %}\n";
+    let doc = parse(input);
+    let errors = resolve_node(&doc.borrow(), &config).expect("resolves");
+    let index = LineIndex::new(input);
+    assert_eq!(
+      errors,
+      vec![
+        Error {
+          id: "undefined-variable",
+          level: ErrorLevel::Warning,
+          message: "Undefined variable: '$page.author'".into(),
+          location: Some(index.location(24..42)),
+        },
+        Error {
+          id: "undefined-function",
+          level: ErrorLevel::Warning,
+          message: "Undefined function: 'today'".into(),
+          location: Some(index.location(49..56)),
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn strict_mode_fails_on_undefined_references() {
+    let config = Config {
+      strict: true,
+      ..Default::default()
+    };
+
+    let doc = parse("Hello {% $name %}\n");
+    let errors = resolve_node(&doc.borrow(), &config).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].id, "undefined-variable");
+    assert_eq!(errors[0].level, ErrorLevel::Error);
+
+    let doc = parse("Hello world\n");
+    assert_eq!(resolve_node(&doc.borrow(), &config), Ok(vec![]));
+  }
+
+  #[test]
+  fn default_arguments_are_not_reported() {
+    let config = Config {
+      strict: true,
+      variables: Variables::Values(mdattrs!(items = Value::Array(vec![]))).into(),
+      ..Default::default()
+    };
+
+    let doc = parse("Hello {% default($name, \"friend\") %}\n");
+    assert_eq!(resolve_node(&doc.borrow(), &config), Ok(vec![]));
+
+    let doc = parse("Hello {% default($items[$index], \"friend\") %}\n");
+    let errors = resolve_node(&doc.borrow(), &config).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "Undefined variable: '$index'");
+
+    let doc = parse("Hello {% equals($name, \"friend\") %}\n");
+    let errors = resolve_node(&doc.borrow(), &config).unwrap_err();
+    assert_eq!(errors[0].id, "undefined-variable");
+  }
+
+  fn truncate_config<'a>() -> Config<'a> {
+    Config {
+      functions: hash!(
+        "truncate" => FunctionSchema {
+          attributes: indexmap!(
+            "0" => Attribute {
+              kind: Some(SchemaType::String),
+              required: true,
+              ..Default::default()
+            },
+            "1" => Attribute {
+              kind: Some(SchemaType::Number),
+              ..Default::default()
+            },
+            "suffix" => Attribute {
+              kind: Some(SchemaType::String),
+              ..Default::default()
+            }
+          )
+          .into(),
+          evaluate: evaluate_fn(|_parameters, _config| "truncated".into()),
+        }
+      )
+      .into(),
+      ..Default::default()
+    }
+  }
+
+  fn resolve_call<'a>(input: &'a str, config: &'a Config<'a>) -> (Value<'a>, Vec<Error>) {
+    match crate::tag::parse(input) {
+      crate::tag::Tag::Value(value) => {
+        let errors = resolve(&value, config).expect("resolves");
+        (value.resolved(Value::clone), errors)
+      }
+      tag => panic!("expected a value, got {:?}", tag),
+    }
+  }
+
+  #[test]
+  fn function_parameters_are_checked() {
+    let config = truncate_config();
+    let ids = |input| {
+      let (value, errors) = resolve_call(input, &config);
+      (
+        value,
+        errors.iter().map(|error| error.id).collect::<Vec<_>>(),
+      )
+    };
+
+    assert_eq!(
+      ids("{% truncate(\"text\", 2, suffix=\"...\") %}"),
+      (Value::from("truncated"), vec![])
+    );
+    assert_eq!(
+      ids("{% truncate(\"text\", 2, 3) %}"),
+      (Value::Undefined, vec!["parameter-count-invalid"])
+    );
+    assert_eq!(
+      ids("{% truncate(\"text\", length=2) %}"),
+      (Value::Undefined, vec!["parameter-undefined"])
+    );
+    assert_eq!(
+      ids("{% truncate(\"text\", \"2\") %}"),
+      (Value::Undefined, vec!["parameter-type-invalid"])
+    );
+    assert_eq!(
+      ids("{% truncate(suffix=\"...\") %}"),
+      (Value::Undefined, vec!["parameter-missing-required"])
+    );
+  }
+
+  #[test]
+  fn function_parameter_errors_are_located() {
+    let config = truncate_config();
+    let input = "Intro\n\nSee {% truncate(1) %}\n";
+    let doc = parse(input);
+    let errors = resolve_node(&doc.borrow(), &config).expect("resolves");
+    assert_eq!(
+      errors,
+      vec![Error {
+        id: "parameter-type-invalid",
+        level: ErrorLevel::Error,
+        message: "Parameter '0' of 'truncate' must be type of 'String'".into(),
+        location: Some(LineIndex::new(input).location(14..25)),
+      }]
+    );
+
+    let input = "{% callout title=\"x\" text=truncate(1) level=2 %}\nBody\n{% /callout %}\n";
+    let doc = parse(input);
+    let errors = resolve_node(&doc.borrow(), &config).expect("resolves");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].level, ErrorLevel::Error);
+    assert_eq!(
+      errors[0].location,
+      Some(LineIndex::new(input).location(26..37))
+    );
+  }
+
+  #[test]
+  fn nested_variable_paths() {
+    let config = Config {
+      variables: Variables::Values(mdattrs!(
+        items = vec!["zero".into(), "one".into(), "two".into()],
+        labels = mdattrs!(en = "Hello", fr = "Bonjour"),
+        lang = "fr",
+        index = 1,
+        order = vec![2.into(), 0.into()]
+      ))
+      .into(),
+      ..Default::default()
+    };
+
+    let (value, errors) = resolve_call("{% $items[$index] %}", &config);
+    assert_eq!((value, errors), (Value::from("one"), vec![]));
+
+    let (value, _) = resolve_call("{% $labels[$lang] %}", &config);
+    assert_eq!(value, Value::from("Bonjour"));
+
+    let (value, _) = resolve_call("{% $items[$order[0]] %}", &config);
+    assert_eq!(value, Value::from("two"));
+
+    let (value, errors) = resolve_call("{% $items[$missing] %}", &config);
+    assert_eq!(value, Value::Undefined);
+    assert_eq!(
+      errors
+        .iter()
+        .map(|x| x.message.as_str())
+        .collect::<Vec<_>>(),
+      vec![
+        "Undefined variable: '$missing'",
+        "Undefined variable: '$items[$missing]'"
+      ]
+    );
+  }
+
+  #[test]
+  fn nested_variable_paths_with_resolver() {
+    let config = Config {
+      variables: Variables::Resolver(variable_fn(|path| match path {
+        [Value::String(name)] if name.as_ref() == "index" => Value::Number(2.0),
+        [Value::String(name), Value::Number(index)] if name.as_ref() == "items" => {
+          format!("item {}", index).into()
+        }
+        _ => Value::Undefined,
+      }))
+      .into(),
+      ..Default::default()
+    };
+
+    let (value, errors) = resolve_call("{% $items[$index] %}", &config);
+    assert_eq!((value, errors), (Value::from("item 2"), vec![]));
+  }
+
+  #[test]
+  fn special_variables_scope() {
+    let config = Config {
+      variables: Variables::Values(mdattrs!(page = "document variable")).into(),
+      special_variables: Variables::Values(mdattrs!(page = mdattrs!(title = "Example"))).into(),
+      ..Default::default()
+    };
+
+    let (value, _) = resolve_call("{% $page %}", &config);
+    assert_eq!(value, Value::from("document variable"));
+
+    let (value, _) = resolve_call("{% @page.title %}", &config);
+    assert_eq!(value, Value::from("Example"));
+
+    let (value, errors) = resolve_call("{% @missing %}", &config);
+    assert_eq!(value, Value::Undefined);
+    assert_eq!(errors[0].message, "Undefined variable: '@missing'");
+  }
+
+  #[test]
+  fn variables_serialize_sigil() {
+    let value: Value = Expression::Variable('@', vec!["page".into(), "title".into()]).into();
+    let json = serde_json::to_value(&value).unwrap();
+    assert_eq!(json["sigil"], "@");
+    assert_eq!(json["path"], serde_json::json!(["page", "title"]));
+  }
+
+  #[test]
+  fn json_config_variables() {
+    let config: Config =
+      serde_json::from_str(r#"{"variables": {"user": {"name": "Ada"}}, "strict": true}"#).unwrap();
+
+    let (value, errors) = resolve_call("{% $user.name %}", &config);
+    assert_eq!((value, errors), (Value::from("Ada"), vec![]));
+
+    let invalid = serde_json::from_str::<Config>(r#"{"variables": [1, 2]}"#);
+    assert!(invalid.is_err());
+  }
 }
@@ -1,14 +1,16 @@
+use crate::functions::condition;
 use crate::model::node::*;
 use crate::model::render::*;
 use crate::model::schema::*;
 use crate::model::value::*;
 use crate::transform::*;
+use indexmap::indexmap;
 use std::collections::HashMap;
 
 pub fn default_nodes<'a>() -> HashMap<Type, Schema<'a>> {
   hash!(
     Type::Document => Schema {
-      attributes: hash!(
+      attributes: indexmap!(
         "frontmatter" => Attribute {
           render: AttributeRender::False,
           ..Default::default()
@@ -18,18 +20,18 @@ pub fn default_nodes<'a>() -> HashMap<Type, Schema<'a>> {
     },
 
     Type::Heading => Schema {
-      attributes: hash!(
+      attributes: indexmap!(
         "level" => Attribute {
-          kind: Some(SchemaType::String),
+          kind: Some(SchemaType::Number),
           render: AttributeRender::False,
           required: true,
         }
       ).into(),
-      transform: Some(|node, config| Renderable::Tag {
+      transform: Some(transform_fn(|node, config| Renderable::Tag {
         name: format!("h{}", node.attribute("level").unwrap()).into(),
         attributes: transform_attributes(&node, &config),
         children: transform_children(&node, &config)
-      }),
+      })),
       ..Default::default()
     },
 
@@ -40,7 +42,7 @@ pub fn default_nodes<'a>() -> HashMap<Type, Schema<'a>> {
 
     Type::Image => Schema {
       render: "img".into(),
-      attributes: hash!(
+      attributes: indexmap!(
         "src" => Attribute {
           kind: Some(SchemaType::String),
           required: true,
@@ -59,8 +61,7 @@ pub fn default_nodes<'a>() -> HashMap<Type, Schema<'a>> {
     },
 
     Type::Fence => Schema {
-      render: "pre".into(),
-      attributes: hash!(
+      attributes: indexmap!(
         "content" => Attribute {
           kind: Some(SchemaType::String),
           render: AttributeRender::False,
@@ -77,6 +78,15 @@ pub fn default_nodes<'a>() -> HashMap<Type, Schema<'a>> {
           ..Default::default()
         }
       ).into(),
+      transform: Some(transform_fn(|node, config| Renderable::Tag {
+        name: "pre".into(),
+        attributes: transform_attributes(node, config),
+        children: vec![Renderable::Tag {
+          name: "code".into(),
+          attributes: None,
+          children: transform_children(node, config)
+        }].into()
+      })),
       ..Default::default()
     },
 
@@ -91,22 +101,27 @@ pub fn default_nodes<'a>() -> HashMap<Type, Schema<'a>> {
     },
 
     Type::List => Schema {
-      attributes: hash!(
+      attributes: indexmap!(
         "ordered" => Attribute {
           kind: Some(SchemaType::Boolean),
           render: AttributeRender::False,
           required: true,
           ..Default::default()
+        },
+        "number" => Attribute {
+          kind: Some(SchemaType::Number),
+          render: AttributeRender::False,
+          ..Default::default()
         }
       ).into(),
-      transform: Some(|node, config| Renderable::Tag {
+      transform: Some(transform_fn(|node, config| Renderable::Tag {
         name: node.attribute("ordered").and_then(|attr| attr.resolved(|value| match value {
           Value::Boolean(true) => Some("ol"),
           _ => None
         })).unwrap_or("ul").into(),
         attributes: transform_attributes(&node, &config),
         children: transform_children(&node, &config)
-      }),
+      })),
       ..Default::default()
     },
 
@@ -127,7 +142,7 @@ pub fn default_nodes<'a>() -> HashMap<Type, Schema<'a>> {
 
     Type::TableHeadCell => Schema {
       render: "th".into(),
-      attributes: hash!(
+      attributes: indexmap!(
         "width" => Attribute {
           kind: Some(SchemaType::Number),
           ..Default::default()
@@ -147,7 +162,7 @@ pub fn default_nodes<'a>() -> HashMap<Type, Schema<'a>> {
 
     Type::TableCell => Schema {
       render: "td".into(),
-      attributes: hash!(
+      attributes: indexmap!(
         "colspan" => Attribute {
           kind: Some(SchemaType::Number),
           ..Default::default()
@@ -181,7 +196,7 @@ pub fn default_nodes<'a>() -> HashMap<Type, Schema<'a>> {
 
     Type::Code => Schema {
       render: "code".into(),
-      attributes: hash!(
+      attributes: indexmap!(
         "content" => Attribute {
           kind: Some(SchemaType::String),
           render: AttributeRender::False,
@@ -189,19 +204,20 @@ pub fn default_nodes<'a>() -> HashMap<Type, Schema<'a>> {
           ..Default::default()
         }
       ).into(),
-      transform: Some(|node, config| Renderable::Tag {
+      transform: Some(transform_fn(|node, config| Renderable::Tag {
         name: "code".into(),
         attributes: transform_attributes(&node, &config),
-        children: vec![
-          Renderable::String("asdf".into()).into() // TODO
-        ].into()
-      }),
+        children: node.attribute("content").and_then(|attr| attr.resolved(|value| match value {
+          Value::String(s) => Some(vec![Renderable::String(s.clone())]),
+          _ => None
+        }))
+      })),
       ..Default::default()
     },
 
     Type::Link => Schema {
       render: "a".into(),
-      attributes: hash!(
+      attributes: indexmap!(
         "href" => Attribute {
           kind: Some(SchemaType::String),
           required: true,
@@ -220,19 +236,19 @@ pub fn default_nodes<'a>() -> HashMap<Type, Schema<'a>> {
     },
 
     Type::Text => Schema {
-      attributes: hash!(
+      attributes: indexmap!(
         "content" => Attribute {
           kind: Some(SchemaType::String),
           required: true,
           ..Default::default()
         }
       ).into(),
-      transform: Some(|node, _config| {
+      transform: Some(transform_fn(|node, _config| {
         node.attribute("content").and_then(|attr| attr.resolved(|value| match value {
           Value::String(s) => Some(Renderable::String(s.clone())),
           _ => None
         })).unwrap_or(Renderable::Null)
-      }),
+      })),
       ..Default::default()
     },
 
@@ -247,10 +263,176 @@ pub fn default_nodes<'a>() -> HashMap<Type, Schema<'a>> {
     },
 
     Type::SoftBreak => Schema {
-      transform: Some(|_node, _config|
+      transform: Some(transform_fn(|_node, _config|
         Renderable::String(" ".into())
-      ),
+      )),
+      ..Default::default()
+    }
+  )
+}
+
+fn transform_if<'a>(node: &Node<'a>, config: &'a Config<'a>) -> Renderable<'a> {
+  let mut matched = condition(node.attribute("primary"));
+  let mut children = Vec::new();
+
+  for child in node.children.iter().flatten() {
+    let child = child.borrow();
+
+    if let (Type::Tag(..), Some("else")) = (&child.kind, child.tag.as_deref()) {
+      if matched {
+        break;
+      }
+
+      matched = match child.attribute("primary") {
+        Some(value) => condition(Some(value)),
+        None => true,
+      };
+    } else if matched {
+      children.push(transform_node(&child, config));
+    }
+  }
+
+  Renderable::Fragment(children)
+}
+
+pub fn default_tags<'a>() -> HashMap<&'a str, Schema<'a>> {
+  hash!(
+    "if" => Schema {
+      attributes: indexmap!(
+        "primary" => Attribute {
+          render: AttributeRender::False,
+          ..Default::default()
+        }
+      ).into(),
+      transform: Some(transform_fn(transform_if)),
+      ..Default::default()
+    },
+
+    "else" => Schema {
+      attributes: indexmap!(
+        "primary" => Attribute {
+          render: AttributeRender::False,
+          ..Default::default()
+        }
+      ).into(),
+      self_closing: true,
+      transform: Some(transform_fn(|_node, _config| Renderable::Null)),
+      ..Default::default()
+    },
+
+    // Children are grafted in from the partial provider while parsing
+    "partial" => Schema {
+      attributes: indexmap!(
+        "file" => Attribute {
+          kind: Some(SchemaType::String),
+          render: AttributeRender::False,
+          required: true,
+        },
+        "variables" => Attribute {
+          kind: Some(SchemaType::Object),
+          render: AttributeRender::False,
+          ..Default::default()
+        }
+      ).into(),
+      self_closing: true,
       ..Default::default()
     }
   )
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::model::Attributes;
+  use crate::parse::{parse_with_options, ParseOptions};
+  use crate::render::html::render;
+  use crate::resolve::resolve_node;
+
+  fn config(variables: Attributes) -> Config {
+    Config {
+      nodes: default_nodes(),
+      tags: default_tags(),
+      variables: Some(Variables::Values(variables)),
+      ..Default::default()
+    }
+  }
+
+  fn render_conditional<'a>(input: &'a str, config: &'a Config<'a>) -> String {
+    let options = ParseOptions {
+      config: Some(config),
+      ..Default::default()
+    };
+
+    let doc = parse_with_options(input, &options);
+    resolve_node(&doc.borrow(), config).expect("resolves");
+
+    let rendered = transform_node(&doc.borrow(), config);
+    let mut output = Vec::new();
+    render(&rendered, &mut output).expect("completes");
+    String::from_utf8(output).unwrap()
+  }
+
+  #[test]
+  fn if_tag() {
+    let input = "{% if $show %}\nShown\n{% /if %}";
+    assert_eq!(
+      render_conditional(input, &config(mdattrs!(show = true))),
+      "<p>Shown</p>"
+    );
+    assert_eq!(
+      render_conditional(input, &config(mdattrs!(show = false))),
+      ""
+    );
+    assert_eq!(render_conditional(input, &config(Attributes::new())), "");
+  }
+
+  #[test]
+  fn if_tag_uses_markdoc_truthiness() {
+    let input = "{% if $value %}\nShown\n{% /if %}";
+    assert_eq!(
+      render_conditional(input, &config(mdattrs!(value = 0))),
+      "<p>Shown</p>"
+    );
+    assert_eq!(
+      render_conditional(input, &config(mdattrs!(value = ""))),
+      "<p>Shown</p>"
+    );
+    assert_eq!(
+      render_conditional(input, &config(mdattrs!(value = Value::Null))),
+      ""
+    );
+  }
+
+  #[test]
+  fn else_branches() {
+    let input = "{% if $pro %}\nPro\n{% else $team /%}\nTeam\n{% else %}\nFree\n{% /if %}";
+    assert_eq!(
+      render_conditional(input, &config(mdattrs!(pro = true, team = true))),
+      "<p>Pro</p>"
+    );
+    assert_eq!(
+      render_conditional(input, &config(mdattrs!(pro = false, team = true))),
+      "<p>Team</p>"
+    );
+    assert_eq!(
+      render_conditional(input, &config(mdattrs!(pro = false, team = false))),
+      "<p>Free</p>"
+    );
+  }
+
+  #[test]
+  fn partial_variables_scope() {
+    let config = Config {
+      partials: Some(Box::new(hash!(
+        "greeting.md" => "Hello {% $name %} from {% $site %} in {% @file %}"
+      ))),
+      ..config(mdattrs!(name = "outer", site = "Docs"))
+    };
+
+    let input = "{% partial file=\"greeting.md\" variables={name: \"inner\"} /%}\n\nBy {% $name %}";
+    assert_eq!(
+      render_conditional(input, &config),
+      "<p>Hello inner from Docs in greeting.md</p><p>By outer</p>"
+    );
+  }
+}
@@ -1,4 +1,5 @@
 use crate::model::{
+  location::LineIndex,
   value::{Expression, Value},
   Attributes,
 };
@@ -22,17 +23,28 @@ pub enum Tag<'a, R: RuleType> {
   Error(Error<R>),
 }
 
+// Where the tag starts in a document, so function calls inside it can record
+// their own locations
+type Origin<'i, 'x> = Option<(&'i LineIndex<'x>, usize)>;
+
 pub fn parse(input: &str) -> Tag<Rule> {
+  parse_at(input, None)
+}
+
+pub fn parse_at<'a>(input: &'a str, origin: Origin) -> Tag<'a, Rule> {
   match TagParser::parse(Rule::Top, input) {
-    Ok(mut pair) => convert_tag(pair.next().unwrap()),
+    Ok(mut pair) => convert_tag(pair.next().unwrap(), origin),
     Err(err) => Tag::Error(err),
   }
 }
 
-fn convert_tag(pair: Pair<Rule>) -> Tag<Rule> {
+fn convert_tag<'a>(pair: Pair<'a, Rule>, origin: Origin) -> Tag<'a, Rule> {
   match pair.as_rule() {
-    Rule::Function | Rule::Variable => Tag::Value(convert_value(pair)),
-    Rule::Annotation => Tag::Annotation(convert_attributes(pair.into_inner().next().unwrap())),
+    Rule::Function | Rule::Variable => Tag::Value(convert_value(pair, origin)),
+    Rule::Annotation => Tag::Annotation(convert_attributes(
+      pair.into_inner().next().unwrap(),
+      origin,
+    )),
     Rule::TagClose => Tag::Close(pair.into_inner().next().unwrap().as_str()),
     Rule::TagOpen => {
       let standalone = pair.as_str().trim().ends_with('/');
@@ -45,11 +57,11 @@ fn convert_tag(pair: Pair<Rule>) -> Tag<Rule> {
           Rule::Primary => {
             attrs.insert(
               "primary".into(),
-              convert_value(item.into_inner().next().unwrap()),
+              convert_value(item.into_inner().next().unwrap(), origin),
             );
           }
           Rule::Attributes => {
-            attrs.extend(convert_attributes(item));
+            attrs.extend(convert_attributes(item, origin));
           }
           _ => (),
         }
@@ -67,7 +79,10 @@ fn convert_tag(pair: Pair<Rule>) -> Tag<Rule> {
   }
 }
 
-fn convert_function(pair: Pair<Rule>) -> Value {
+fn convert_function<'a>(pair: Pair<'a, Rule>, origin: Origin) -> Value<'a> {
+  let span = pair.as_span();
+  let location =
+    origin.map(|(index, offset)| index.location(offset + span.start()..offset + span.end()));
   let mut inner = pair.into_inner();
   let name = inner.next().unwrap().as_str();
   let attrs: Attributes = inner
@@ -76,64 +91,86 @@ fn convert_function(pair: Pair<Rule>) -> Value {
       Rule::Attribute => {
         let mut inner = item.into_inner();
         let key = inner.next().unwrap().as_str();
-        let value = convert_value(inner.next().unwrap());
+        let value = convert_value(inner.next().unwrap(), origin);
         (key.into(), value)
       }
-      _ => (CowStr::from(index.to_string()), convert_value(item)),
+      _ => (CowStr::from(index.to_string()), convert_value(item, origin)),
     })
     .collect();
 
-  Expression::Function(name.into(), attrs).into()
+  Expression::Function(name.into(), attrs, location).into()
 }
 
-fn convert_attributes(pair: Pair<Rule>) -> Attributes {
-  let mut classes = Attributes::new();
-  let mut attributes: Attributes = pair
-    .into_inner()
-    .filter_map(|item| {
-      let mut inner = item.into_inner();
-      let key = inner.next().unwrap().as_str();
-      let value = inner.next().unwrap();
-
-      match key {
-        "." | "class" => {
-          classes.insert(value.as_str().into(), Value::Boolean(true));
-          None
-        }
-        "#" => Some(("id".into(), convert_value(value))),
-        _ => Some((key.into(), convert_value(value))),
-      }
-    })
-    .collect();
+fn convert_attributes<'a>(pair: Pair<'a, Rule>, origin: Origin) -> Attributes<'a> {
+  let mut attributes = Attributes::new();
 
-  if !classes.is_empty() {
-    attributes.insert("class".into(), Value::Hash(classes));
+  for item in pair.into_inner() {
+    let mut inner = item.into_inner();
+    let key = inner.next().unwrap().as_str();
+    let value = inner.next().unwrap();
+
+    match key {
+      "." => {
+        let class = Value::Hash([(value.as_str().into(), Value::Boolean(true))].into());
+        merge_class(&mut attributes, class);
+      }
+      "class" => merge_class(&mut attributes, convert_value(value, origin)),
+      "#" => {
+        attributes.insert("id".into(), convert_value(value, origin));
+      }
+      _ => {
+        attributes.insert(key.into(), convert_value(value, origin));
+      }
+    }
   }
 
   attributes
 }
 
-fn convert_value(pair: Pair<Rule>) -> Value {
+fn merge_class<'a>(attributes: &mut Attributes<'a>, class: Value<'a>) {
+  match (attributes.get_mut("class"), class) {
+    (Some(Value::Hash(classes)), Value::Hash(other)) => classes.extend(other),
+    (Some(Value::Array(classes)), class) => classes.push(class),
+    (Some(existing), class) => {
+      let first = std::mem::replace(existing, Value::Null);
+      *existing = Value::Array(vec![first, class]);
+    }
+    (None, class) => {
+      attributes.insert("class".into(), class);
+    }
+  }
+}
+
+fn convert_value<'a>(pair: Pair<'a, Rule>, origin: Origin) -> Value<'a> {
   match pair.as_rule() {
     Rule::Variable => Expression::Variable(
       pair.as_str().chars().next().unwrap(),
-      pair.into_inner().map(convert_value).collect(),
+      pair
+        .into_inner()
+        .map(|item| convert_value(item, origin))
+        .collect(),
     )
     .into(),
-    Rule::Function => convert_function(pair),
+    Rule::Function => convert_function(pair, origin),
     Rule::ValueNull => Value::Null,
-    Rule::Identifier | Rule::ValueString => pair.as_str().into(),
+    Rule::Identifier => pair.as_str().into(),
+    Rule::ValueString => unescape(pair.as_str()).into(),
     Rule::ValueNumber => Value::Number(pair.as_str().parse().unwrap()),
     Rule::ValueBoolean => Value::Boolean(pair.as_str().parse().unwrap()),
-    Rule::ValueArray => Value::Array(pair.into_inner().map(convert_value).collect()),
+    Rule::ValueArray => Value::Array(
+      pair
+        .into_inner()
+        .map(|item| convert_value(item, origin))
+        .collect(),
+    ),
     Rule::ValueHash => Value::Hash(
       pair
         .into_inner()
         .map(|item| {
           let mut inner = item.into_inner();
-          let key = inner.next().unwrap().as_str();
-          let value = convert_value(inner.next().unwrap());
-          (key.into(), value)
+          let key = unescape(inner.next().unwrap().as_str());
+          let value = convert_value(inner.next().unwrap(), origin);
+          (key, value)
         })
         .collect(),
     ),
@@ -141,6 +178,23 @@ fn convert_value(pair: Pair<Rule>) -> Value {
   }
 }
 
+// The grammar only allows `\"` and `\\` escapes in strings
+fn unescape(value: &str) -> CowStr<'_> {
+  if !value.contains('\\') {
+    return value.into();
+  }
+
+  let mut output = String::with_capacity(value.len());
+  let mut chars = value.chars();
+  while let Some(ch) = chars.next() {
+    match ch {
+      '\\' => output.extend(chars.next()),
+      ch => output.push(ch),
+    }
+  }
+  output.into()
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -234,7 +288,7 @@ mod tests {
       .unwrap();
 
     assert_eq!(
-      convert_value(pair),
+      convert_value(pair, None),
       vec![1.into(), 2.into(), 3.into()].into()
     );
   }
@@ -247,7 +301,7 @@ mod tests {
       .unwrap();
 
     assert_eq!(
-      convert_value(pair),
+      convert_value(pair, None),
       vec![1.into(), 2.into(), 3.into()].into()
     );
   }
@@ -260,7 +314,7 @@ mod tests {
       .unwrap();
 
     assert_eq!(
-      convert_value(pair),
+      convert_value(pair, None),
       mdattrs!(foo = "bar", baz = true).into()
     )
   }
@@ -273,7 +327,7 @@ mod tests {
       .unwrap();
 
     assert_eq!(
-      convert_attributes(pair),
+      convert_attributes(pair, None),
       [
         ("foo".into(), true.into()),
         ("bar".into(), vec![1.into(), 2.into(), 3.into()].into())
@@ -290,7 +344,7 @@ mod tests {
       .unwrap();
 
     assert_eq!(
-      convert_attributes(pair),
+      convert_attributes(pair, None),
       [("foo".into(), true.into()), ("bar".into(), "".into())].into()
     )
   }
@@ -303,7 +357,7 @@ mod tests {
       .unwrap();
 
     assert_eq!(
-      convert_attributes(pair),
+      convert_attributes(pair, None),
       mdattrs!(
         asdf = 1,
         id = "foo",
@@ -312,6 +366,42 @@ mod tests {
     )
   }
 
+  #[test]
+  fn convert_attributes_in_source_order() {
+    let pair = TagParser::parse(Rule::Attributes, "zeta=1 .foo alpha=2 #bar .baz")
+      .expect("parse failed")
+      .next()
+      .unwrap();
+
+    let attributes = convert_attributes(pair, None);
+    assert_eq!(
+      attributes
+        .keys()
+        .map(|key| key.as_ref())
+        .collect::<Vec<_>>(),
+      vec!["zeta", "class", "alpha", "id"]
+    );
+  }
+
+  #[test]
+  fn convert_class_attribute_with_shortcuts() {
+    let pair = TagParser::parse(Rule::Attributes, ".foo class={active: $active} .bar")
+      .expect("parse failed")
+      .next()
+      .unwrap();
+
+    assert_eq!(
+      convert_attributes(pair, None),
+      mdattrs!(
+        class = mdattrs!(
+          foo = true,
+          active = Expression::Variable('$', vec!["active".into()]),
+          bar = true
+        )
+      )
+    )
+  }
+
   #[test]
   fn convert_variable() {
     let pair = TagParser::parse(Rule::Variable, "$foo.bar[10].baz")
@@ -320,7 +410,7 @@ mod tests {
       .unwrap();
 
     assert_eq!(
-      convert_value(pair),
+      convert_value(pair, None),
       Expression::Variable(
         '$',
         vec!["foo".into(), "bar".into(), 10.into(), "baz".into()]
@@ -337,7 +427,7 @@ mod tests {
       .unwrap();
 
     assert_eq!(
-      convert_function(pair),
+      convert_function(pair, None),
       Expression::Function(
         "foo".into(),
         [
@@ -345,7 +435,8 @@ mod tests {
           ("bar".into(), true.into()),
           ("2".into(), 3.into()),
         ]
-        .into()
+        .into(),
+        None
       )
       .into()
     )
@@ -367,7 +458,8 @@ mod tests {
                 "0".into(),
                 Expression::Variable('$', vec!["test".into()]).into()
               )]
-              .into()
+              .into(),
+              None
             )
             .into()
           )]
@@ -376,4 +468,22 @@ mod tests {
       )
     )
   }
+
+  #[test]
+  fn string_escapes() {
+    let tag = parse(r#"{% foo bar="a \"b\" \\ c" baz={"d\"": 1} %}"#);
+    assert_eq!(
+      tag,
+      Tag::Open(
+        "foo",
+        Some(
+          [
+            ("bar".into(), r#"a "b" \ c"#.into()),
+            ("baz".into(), Value::Hash([("d\"".into(), 1.into())].into())),
+          ]
+          .into()
+        )
+      )
+    )
+  }
 }
@@ -9,27 +9,34 @@ pub fn find_schema<'a>(node: &Node<'a>, config: &'a Config<'a>) -> Option<&'a Sc
 }
 
 pub fn transform_attributes<'a>(node: &Node<'a>, config: &'a Config<'a>) -> Option<Attributes<'a>> {
-  if let Some(attrs) = find_schema(&node, &config).and_then(|schema| schema.attributes.as_ref()) {
-    let mut output = Attributes::new();
-
-    for (key, attr) in attrs {
-      let name = match attr.render {
-        AttributeRender::True => key,
-        AttributeRender::Name(n) => n,
-        AttributeRender::False => continue,
-      };
+  let attrs = find_schema(node, config).and_then(|schema| schema.attributes.as_ref());
+  let mut output = Attributes::new();
 
-      if let Some(value) = node.attribute(key) {
-        output.insert(name.into(), value.clone());
-      }
+  for (key, attr) in attrs.into_iter().flatten() {
+    let name = match attr.render {
+      AttributeRender::True => key,
+      AttributeRender::Name(n) => n,
+      AttributeRender::False => continue,
+    };
+
+    if let Some(value) = node.attribute(key) {
+      output.insert((*name).into(), value.clone());
     }
+  }
+
+  for key in GLOBAL_ATTRIBUTES {
+    let declared = matches!(attrs, Some(attrs) if attrs.contains_key(key));
 
-    if !output.is_empty() {
-      return output.into();
+    if let (false, Some(value)) = (declared, node.attribute(key)) {
+      output.insert((*key).into(), value.clone());
     }
   }
 
-  None
+  if output.is_empty() {
+    None
+  } else {
+    Some(output)
+  }
 }
 
 pub fn transform_children<'a>(
@@ -48,7 +55,7 @@ pub fn transform_children<'a>(
 
 pub fn transform_node<'a>(node: &Node<'a>, config: &'a Config<'a>) -> Renderable<'a> {
   if let Some(schema) = find_schema(&node, &config) {
-    if let Some(transform_func) = schema.transform {
+    if let Some(transform_func) = &schema.transform {
       return transform_func(&node, &config);
     }
 
@@ -65,7 +72,103 @@ pub fn transform_node<'a>(node: &Node<'a>, config: &'a Config<'a>) -> Renderable
     if let Some(children) = children {
       return Renderable::Fragment(children);
     }
+  } else if let (Type::Tag(inline), Some(tag)) = (&node.kind, &node.tag) {
+    return match config.unknown_tags {
+      UnknownTagPolicy::Drop => Renderable::Null,
+      UnknownTagPolicy::Children => {
+        Renderable::Fragment(transform_children(node, config).unwrap_or_default())
+      }
+      UnknownTagPolicy::Placeholder => Renderable::Tag {
+        name: if *inline { "span" } else { "div" }.into(),
+        attributes: Attributes::from([
+          ("class".into(), "markdoc-error".into()),
+          ("data-tag".into(), tag.clone().into()),
+        ])
+        .into(),
+        children: transform_children(node, config),
+      },
+    };
   }
 
   Renderable::Null
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{parse::parse, render::html::render, schema::default_nodes};
+
+  fn render_with_policy(input: &str, policy: UnknownTagPolicy) -> String {
+    let config = Config {
+      nodes: default_nodes(),
+      unknown_tags: policy,
+      ..Default::default()
+    };
+
+    let doc = parse(input);
+    let rendered = transform_node(&doc.borrow(), &config);
+    let mut output = Vec::new();
+    render(&rendered, &mut output).expect("completes");
+    String::from_utf8(output).unwrap()
+  }
+
+  #[test]
+  fn unknown_tag_dropped() {
+    let output = render_with_policy(
+      "{% calout %}\nWarning\n{% /calout %}",
+      UnknownTagPolicy::Drop,
+    );
+    assert_eq!(output, "");
+  }
+
+  #[test]
+  fn unknown_tag_renders_children() {
+    let output = render_with_policy(
+      "{% calout %}\nWarning\n{% /calout %}",
+      UnknownTagPolicy::Children,
+    );
+    assert_eq!(output, "<p>Warning</p>");
+  }
+
+  #[test]
+  fn unknown_tag_renders_placeholder() {
+    let output = render_with_policy(
+      "{% calout %}\nWarning\n{% /calout %}",
+      UnknownTagPolicy::Placeholder,
+    );
+    assert_eq!(
+      output,
+      r#"<div class="markdoc-error" data-tag="calout"><p>Warning</p></div>"#
+    );
+  }
+
+  #[test]
+  fn transform_captures_state() {
+    let prefix = String::from("https://example.com/docs/");
+    let config = Config {
+      nodes: default_nodes(),
+      tags: hash!(
+        "doc" => Schema {
+          transform: Some(transform_fn(move |node, _config| Renderable::Tag {
+            name: "a".into(),
+            attributes: node.attribute("primary").map(|page| {
+              Attributes::from([("href".into(), format!("{}{}", prefix, page).into())])
+            }),
+            children: None,
+          })),
+          ..Default::default()
+        }
+      ),
+      ..Default::default()
+    };
+
+    let doc = parse("See {% doc \"intro\" /%}");
+    let rendered = transform_node(&doc.borrow(), &config);
+    let mut output = Vec::new();
+    render(&rendered, &mut output).expect("completes");
+    assert_eq!(
+      String::from_utf8(output).unwrap(),
+      r#"<p>See <a href="https://example.com/docs/intro"></a></p>"#
+    );
+  }
+}
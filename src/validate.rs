@@ -0,0 +1,289 @@
+use crate::model::{
+  node::{Error, ErrorLevel, Node, Type},
+  schema::{Config, SchemaType, GLOBAL_ATTRIBUTES},
+  value::Value,
+};
+use crate::transform::find_schema;
+
+pub fn validate<'a>(node: &Node<'a>, config: &'a Config<'a>) -> Vec<Error> {
+  let mut errors = Vec::new();
+  validate_node(node, config, &mut errors);
+  errors
+}
+
+fn validate_node<'a>(node: &Node<'a>, config: &'a Config<'a>, errors: &mut Vec<Error>) {
+  // Tags the parser already reported, like a stray closing tag, aren't
+  // reported again as undefined
+  if let (Type::Tag(..), Some(tag), None) = (&node.kind, &node.tag, &node.errors) {
+    if !config.tags.contains_key(tag.as_ref()) {
+      errors.push(Error {
+        id: "undefined-tag",
+        level: ErrorLevel::Critical,
+        message: format!("Undefined tag: '{}'", tag),
+        location: node.location.clone(),
+      });
+    }
+  }
+
+  if let Some(schema) = find_schema(node, config) {
+    // Partials are self-closing in source but carry the grafted document
+    let grafted = node.tag.as_deref() == Some("partial");
+
+    if schema.self_closing && !grafted && node.children.is_some() {
+      errors.push(Error {
+        id: "self-closing-children",
+        level: ErrorLevel::Error,
+        message: format!(
+          "Tag '{}' is self-closing and can't have children",
+          node.tag.as_deref().unwrap_or_default()
+        ),
+        location: node.location.clone(),
+      });
+    }
+
+    let declared = schema.attributes.as_ref();
+
+    if let Some(attributes) = &node.attributes {
+      for (key, value) in attributes {
+        match declared.and_then(|attrs| attrs.get(key.as_ref())) {
+          Some(attr) => {
+            if let Some(kind) = &attr.kind {
+              if !type_matches(kind, value) {
+                errors.push(Error {
+                  id: "attribute-type-invalid",
+                  level: ErrorLevel::Error,
+                  message: format!("Attribute '{}' must be type of '{:?}'", key, kind),
+                  location: node.location.clone(),
+                });
+              }
+            }
+          }
+
+          None if GLOBAL_ATTRIBUTES.contains(&key.as_ref()) => (),
+
+          None => errors.push(Error {
+            id: "attribute-undefined",
+            level: ErrorLevel::Error,
+            message: format!("Invalid attribute: '{}'", key),
+            location: node.location.clone(),
+          }),
+        }
+      }
+    }
+
+    for (key, attr) in declared.into_iter().flatten() {
+      if attr.required && node.attribute(key).is_none() {
+        errors.push(Error {
+          id: "attribute-missing-required",
+          level: ErrorLevel::Error,
+          message: format!("Missing required attribute: '{}'", key),
+          location: node.location.clone(),
+        });
+      }
+    }
+  }
+
+  if let Some(children) = &node.children {
+    for child in children {
+      validate_node(&child.borrow(), config, errors);
+    }
+  }
+}
+
+pub fn type_matches(kind: &SchemaType, value: &Value) -> bool {
+  match (kind, value) {
+    // Unresolved expressions can't be checked until the config supplies a value
+    (_, Value::Expression(resolved, _)) => match &*resolved.borrow() {
+      Value::Undefined => true,
+      resolved => type_matches(kind, resolved),
+    },
+    (SchemaType::String, Value::String(_)) => true,
+    (SchemaType::Number, Value::Number(_)) => true,
+    (SchemaType::Boolean, Value::Boolean(_)) => true,
+    (SchemaType::Object, Value::Hash(_)) => true,
+    (SchemaType::Array, Value::Array(_)) => true,
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::model::location::LineIndex;
+  use crate::model::schema::{Attribute, Schema};
+  use crate::parse::parse;
+  use crate::schema::default_nodes;
+  use indexmap::indexmap;
+
+  fn config<'a>() -> Config<'a> {
+    Config {
+      tags: hash!(
+        "foo" => Schema {
+          render: "foo".into(),
+          attributes: indexmap!(
+            "bar" => Attribute {
+              kind: Some(SchemaType::String),
+              ..Default::default()
+            },
+            "baz" => Attribute {
+              kind: Some(SchemaType::Number),
+              required: true,
+              ..Default::default()
+            }
+          )
+          .into(),
+          ..Default::default()
+        },
+        "img" => Schema {
+          render: "img".into(),
+          self_closing: true,
+          ..Default::default()
+        }
+      ),
+      nodes: default_nodes(),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn valid_document() {
+    let config = config();
+    let doc =
+      parse("# Heading\n\n{% foo bar=\"test\" baz=1 .example %}\nThis is a *test*\n{% /foo %}\n");
+    let errors = validate(&doc.borrow(), &config);
+    assert_eq!(errors, vec![]);
+  }
+
+  #[test]
+  fn invalid_attribute_type() {
+    let config = config();
+    let input = "{% foo bar=1 baz=1 /%}";
+    let doc = parse(input);
+    let errors = validate(&doc.borrow(), &config);
+    assert_eq!(
+      errors,
+      vec![Error {
+        id: "attribute-type-invalid",
+        level: ErrorLevel::Error,
+        message: "Attribute 'bar' must be type of 'String'".into(),
+        location: Some(LineIndex::new(input).location(0..22)),
+      }]
+    );
+  }
+
+  #[test]
+  fn missing_required_attribute() {
+    let config = config();
+    let input = "{% foo bar=\"test\" /%}";
+    let doc = parse(input);
+    let errors = validate(&doc.borrow(), &config);
+    assert_eq!(
+      errors,
+      vec![Error {
+        id: "attribute-missing-required",
+        level: ErrorLevel::Error,
+        message: "Missing required attribute: 'baz'".into(),
+        location: Some(LineIndex::new(input).location(0..21)),
+      }]
+    );
+  }
+
+  #[test]
+  fn undefined_attribute() {
+    let config = config();
+    let input = "{% foo baz=1 qux=true /%}";
+    let doc = parse(input);
+    let errors = validate(&doc.borrow(), &config);
+    assert_eq!(
+      errors,
+      vec![Error {
+        id: "attribute-undefined",
+        level: ErrorLevel::Error,
+        message: "Invalid attribute: 'qux'".into(),
+        location: Some(LineIndex::new(input).location(0..25)),
+      }]
+    );
+  }
+
+  #[test]
+  fn undefined_tag() {
+    let config = config();
+    let input = "{% calout %}\nWarning\n{% /calout %}";
+    let doc = parse(input);
+    let errors = validate(&doc.borrow(), &config);
+    assert_eq!(
+      errors,
+      vec![Error {
+        id: "undefined-tag",
+        level: ErrorLevel::Critical,
+        message: "Undefined tag: 'calout'".into(),
+        location: Some(LineIndex::new(input).location(0..12)),
+      }]
+    );
+  }
+
+  #[test]
+  fn undefined_tag_with_parse_error() {
+    let config = config();
+    let doc = parse("Text\n\n{% /calout %}\n");
+    assert_eq!(validate(&doc.borrow(), &config), vec![]);
+
+    let doc = parse("{% calout %}\nWarning\n");
+    assert_eq!(validate(&doc.borrow(), &config), vec![]);
+  }
+
+  #[test]
+  fn self_closing_tag_with_children() {
+    let config = config();
+    let input = "{% img %}\nCaption\n{% /img %}";
+    let doc = parse(input);
+    let errors = validate(&doc.borrow(), &config);
+    assert_eq!(
+      errors,
+      vec![Error {
+        id: "self-closing-children",
+        level: ErrorLevel::Error,
+        message: "Tag 'img' is self-closing and can't have children".into(),
+        location: Some(LineIndex::new(input).location(0..9)),
+      }]
+    );
+  }
+
+  #[test]
+  fn self_closing_tag_parsed_without_config() {
+    let config = config();
+    let input = "{% img %}\n\nCaption\n";
+    let doc = parse(input);
+    let errors = validate(&doc.borrow(), &config);
+    let location = Some(LineIndex::new(input).location(0..9));
+    assert_eq!(
+      errors,
+      vec![Error {
+        id: "self-closing-children",
+        level: ErrorLevel::Error,
+        message: "Tag 'img' is self-closing and can't have children".into(),
+        location: location.clone(),
+      }]
+    );
+
+    // The tag is left open while parsing, since nothing marked it self-closing
+    let img = doc.borrow().children.as_ref().unwrap()[0].clone();
+    assert_eq!(
+      img.borrow().errors,
+      Some(vec![Error {
+        id: "missing-closing",
+        level: ErrorLevel::Critical,
+        message: "Tag 'img' is missing closing".into(),
+        location,
+      }])
+    );
+  }
+
+  #[test]
+  fn unresolved_variable_skips_type_check() {
+    let config = config();
+    let doc = parse("{% foo bar=$missing baz=1 /%}");
+    let errors = validate(&doc.borrow(), &config);
+    assert_eq!(errors, vec![]);
+  }
+}
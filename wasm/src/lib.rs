@@ -1,4 +1,10 @@
-use markdoc::parse;
+use markdoc::model::node::Node;
+use markdoc::model::render::Renderable;
+use markdoc::model::schema::Config;
+use markdoc::parse::ParseOptions;
+use markdoc::schema::{default_nodes, default_tags};
+use markdoc::{parse, resolve, transform, validate};
+use serde::Serialize;
 use serde_json;
 use wasm_bindgen::prelude::*;
 
@@ -6,4 +12,61 @@ use wasm_bindgen::prelude::*;
 pub fn parse(input: &str) -> String {
     let doc = parse::parse(input);
     serde_json::to_string(&doc).unwrap()
-}
\ No newline at end of file
+}
+
+#[derive(Serialize)]
+struct Transformed<'a> {
+    content: Renderable<'a>,
+    errors: Vec<serde_json::Value>,
+}
+
+// Returns the render tree as Markdoc `Tag` JSON for the JS renderers, along
+// with the parse, validation and resolution errors. `config` is a JSON
+// Markdoc config whose `nodes` and `tags` extend the built-in ones, and
+// whose `variables` object is resolved as `$name`.
+#[wasm_bindgen]
+pub fn transform(input: &str, config: &str) -> Result<String, JsValue> {
+    let mut config: Config =
+        serde_json::from_str(config).map_err(|error| JsValue::from_str(&error.to_string()))?;
+
+    let mut nodes = default_nodes();
+    nodes.extend(std::mem::take(&mut config.nodes));
+    config.nodes = nodes;
+
+    let mut tags = default_tags();
+    tags.extend(std::mem::take(&mut config.tags));
+    config.tags = tags;
+
+    let options = ParseOptions {
+        config: Some(&config),
+        ..Default::default()
+    };
+
+    let doc = parse::parse_with_options(input, &options);
+    let node = doc.borrow();
+
+    let mut errors = Vec::new();
+    node_errors(&node, &mut errors);
+
+    let validation = validate::validate(&node, &config);
+    let resolution = match resolve::resolve_node(&node, &config) {
+        Ok(errors) | Err(errors) => errors,
+    };
+
+    for error in validation.iter().chain(&resolution) {
+        errors.push(serde_json::to_value(error).unwrap());
+    }
+
+    let content = transform::transform_node(&node, &config);
+    Ok(serde_json::to_string(&Transformed { content, errors }).unwrap())
+}
+
+fn node_errors(node: &Node, errors: &mut Vec<serde_json::Value>) {
+    for error in node.errors.iter().flatten() {
+        errors.push(serde_json::to_value(error).unwrap());
+    }
+
+    for child in node.children.iter().flatten() {
+        node_errors(&child.borrow(), errors);
+    }
+}